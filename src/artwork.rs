@@ -0,0 +1,225 @@
+//! Types and helpers shared by the artwork providers.
+
+use clap::ValueEnum;
+use reqwest::{header::CONTENT_TYPE, RequestBuilder};
+use std::path::{Path, PathBuf};
+
+use crate::{
+    binary::{read_u16_be, read_u16_le, read_u32_be},
+    http::HttpClient,
+    matching::similarity,
+    save_bytes_to_file,
+};
+
+/// Search results scoring below this are treated as "no match" rather than
+/// risking the cover of a different album.
+pub const MIN_MATCH_SCORE: f64 = 0.6;
+
+/// Album cover fallbacks tried, in order, after Tidal.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Provider {
+    #[value(name = "musicbrainz")]
+    MusicBrainz,
+    Deezer,
+    Itunes,
+    Discogs,
+}
+
+impl Provider {
+    pub fn name(self) -> &'static str {
+        match self {
+            Provider::MusicBrainz => "musicbrainz",
+            Provider::Deezer => "deezer",
+            Provider::Itunes => "itunes",
+            Provider::Discogs => "discogs",
+        }
+    }
+}
+
+/// Preferred size of the downloaded cover.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CoverSize {
+    #[value(name = "500")]
+    Small,
+    #[default]
+    #[value(name = "1200")]
+    Large,
+    #[value(name = "original")]
+    Original,
+}
+
+impl CoverSize {
+    /// Edge length in pixels, or `None` for the largest size available.
+    pub fn pixels(self) -> Option<u32> {
+        match self {
+            CoverSize::Small => Some(500),
+            CoverSize::Large => Some(1200),
+            CoverSize::Original => None,
+        }
+    }
+}
+
+/// What we know about the local album from its tags.
+pub struct AlbumQuery<'a> {
+    pub artist: &'a str,
+    pub album: &'a str,
+    pub track_count: usize,
+    pub year: Option<i32>,
+    /// `MUSICBRAINZ_ALBUMID` tag, as written by Picard.
+    pub release_id: Option<&'a str>,
+    /// `MUSICBRAINZ_RELEASEGROUPID` tag, as written by Picard.
+    pub release_group_id: Option<&'a str>,
+    /// `CATALOGNUMBER` tag.
+    pub catno: Option<&'a str>,
+}
+
+impl AlbumQuery<'_> {
+    /// Weighted match score in the range `0.0..=1.0` for a search result with
+    /// the given artist, album title and track count.
+    pub fn match_score(&self, artist: &str, album: &str, track_count: Option<u32>) -> f64 {
+        let track_count_score = match track_count {
+            Some(count) if self.track_count > 0 => {
+                let diff = (count as f64 - self.track_count as f64).abs();
+                (1.0 - diff / self.track_count as f64).max(0.0)
+            }
+            _ => 0.5,
+        };
+
+        similarity(artist, self.artist) * 0.4
+            + similarity(album, self.album) * 0.45
+            + track_count_score * 0.15
+    }
+}
+
+/// Picks the best-scoring of a provider's search `results`. If there are none,
+/// or the best one scores below [`MIN_MATCH_SCORE`], the search `request` is
+/// cached as a miss instead. `kind` names a result in the log ("Deezer
+/// album"), and `describe` tells one from another.
+pub fn best_match<'a, T>(
+    client: &HttpClient,
+    provider: &str,
+    request: &RequestBuilder,
+    kind: &str,
+    results: &'a [T],
+    score: impl Fn(&T) -> f64,
+    describe: impl Fn(&T) -> String,
+) -> Option<&'a T> {
+    let Some((result, score)) = results
+        .iter()
+        .map(|result| (result, score(result)))
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+    else {
+        debugln!("No {kind}s found");
+        client.cache_no_match(provider, request);
+        return None;
+    };
+
+    if score < MIN_MATCH_SCORE {
+        debugln!(
+            "Best {kind} {} scored {:.2}, below threshold {:.2}",
+            describe(result),
+            score,
+            MIN_MATCH_SCORE
+        );
+        client.cache_no_match(provider, request);
+        return None;
+    }
+
+    outln!("Matched {kind} {} (score {:.2})", describe(result), score);
+
+    Some(result)
+}
+
+/// Maps an image `Content-Type` to the file extension it should be saved with.
+pub fn extension_for_content_type(content_type: &str) -> Option<&'static str> {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    match mime.as_str() {
+        "image/jpeg" | "image/jpg" | "image/pjpeg" => Some("jpg"),
+        "image/png" => Some("png"),
+        "image/gif" => Some("gif"),
+        "image/webp" => Some("webp"),
+        "image/bmp" => Some("bmp"),
+        "image/tiff" => Some("tiff"),
+        _ => None,
+    }
+}
+
+/// Width and height of the PNG, JPEG or GIF image at `path`, read from its
+/// header.
+pub fn image_dimensions(path: &Path) -> Option<(u32, u32)> {
+    let data = std::fs::read(path).ok()?;
+    let be16 = |at: usize| read_u16_be(&data, at).map(u32::from);
+
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Some((read_u32_be(&data, 16)?, read_u32_be(&data, 20)?));
+    }
+
+    if data.starts_with(b"GIF8") {
+        let le16 = |at: usize| read_u16_le(&data, at).map(u32::from);
+        return Some((le16(6)?, le16(8)?));
+    }
+
+    if data.starts_with(&[0xff, 0xd8]) {
+        let mut at = 2;
+        while *data.get(at)? == 0xff {
+            let marker = *data.get(at + 1)?;
+            // Start of frame: baseline, progressive and the rarer variants.
+            if matches!(marker, 0xc0..=0xcf) && !matches!(marker, 0xc4 | 0xc8 | 0xcc) {
+                return Some((be16(at + 7)?, be16(at + 5)?));
+            }
+            at += 2 + be16(at + 2)? as usize;
+        }
+    }
+
+    None
+}
+
+/// Downloads the image at `url` into `dir` as `<file_stem>.<ext>`, with the
+/// extension derived from the response's `Content-Type`. Returns the path the
+/// image was saved to.
+pub async fn download_image(
+    client: &HttpClient,
+    provider: &str,
+    url: &str,
+    dir: &Path,
+    file_stem: &str,
+) -> Option<PathBuf> {
+    debugln!("Fetching from {url}");
+
+    let resp = client.send(provider, client.get(url)).await?;
+
+    if !resp.status().is_success() {
+        errln!("Failed to fetch image {url}: status {}", resp.status());
+        return None;
+    }
+
+    let content_type = resp
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+
+    let Some(extension) = extension_for_content_type(&content_type) else {
+        errln!("Unexpected content type '{content_type}' for image {url}");
+        return None;
+    };
+
+    match resp.bytes().await {
+        Ok(bytes) => {
+            let file_path = dir.join(format!("{file_stem}.{extension}"));
+            save_bytes_to_file(&bytes, &file_path);
+            Some(file_path)
+        }
+        Err(error) => {
+            errln!("Deserialization failure {:?}", error);
+            None
+        }
+    }
+}
@@ -0,0 +1,29 @@
+//! Integers read out of the headers of audio and image files.
+
+macro_rules! read_int {
+    ($name:ident, $int:ty, $from_bytes:ident) => {
+        /// The integer at `offset` in `bytes`, or `None` past their end.
+        pub fn $name(bytes: &[u8], offset: usize) -> Option<$int> {
+            const SIZE: usize = std::mem::size_of::<$int>();
+            Some(<$int>::$from_bytes(
+                bytes.get(offset..offset + SIZE)?.try_into().ok()?,
+            ))
+        }
+    };
+}
+
+read_int!(read_u16_le, u16, from_le_bytes);
+read_int!(read_u16_be, u16, from_be_bytes);
+read_int!(read_u32_le, u32, from_le_bytes);
+read_int!(read_u32_be, u32, from_be_bytes);
+read_int!(read_u64_le, u64, from_le_bytes);
+read_int!(read_u64_be, u64, from_be_bytes);
+
+/// Size of the ID3v2 tag starting with `header` (at least its 10-byte
+/// header), not counting the header. It's stored "syncsafe": 7 bits in each
+/// of 4 bytes.
+pub fn id3v2_size(header: &[u8]) -> u64 {
+    header[6..10]
+        .iter()
+        .fold(0u64, |size, byte| (size << 7) | u64::from(byte & 0x7f))
+}
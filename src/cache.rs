@@ -0,0 +1,159 @@
+//! Persistent on-disk cache of provider responses.
+//!
+//! Each entry is a small JSON file under `<cache dir>/http/<provider>/`, named
+//! by the SHA-256 of its key (usually the request URL). Besides successful
+//! responses the cache records misses (404s and "no acceptable match"
+//! results) so albums that failed to match on a previous run aren't looked up
+//! again until the negative TTL runs out.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{read_dir_paths, save_bytes_to_file};
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Entry {
+    provider: String,
+    /// Unix timestamp (seconds) after which the entry is stale.
+    expires_at: u64,
+    /// `None` records a miss.
+    body: Option<serde_json::Value>,
+}
+
+/// A cache lookup that found a fresh entry.
+pub enum Cached {
+    Hit(serde_json::Value),
+    Miss,
+}
+
+pub struct Cache {
+    dir: PathBuf,
+    ttl: Duration,
+    negative_ttl: Duration,
+    /// Ignore existing entries (but still write fresh ones).
+    refresh: bool,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Default cache location: `$XDG_CACHE_HOME/moosicbox_organizer`.
+pub fn default_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("moosicbox_organizer"))
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf, ttl: Duration, negative_ttl: Duration, refresh: bool) -> Self {
+        Self {
+            dir,
+            ttl,
+            negative_ttl,
+            refresh,
+        }
+    }
+
+    fn http_dir(&self) -> PathBuf {
+        self.dir.join("http")
+    }
+
+    fn entry_path(&self, provider: &str, key: &str) -> PathBuf {
+        let digest = Sha256::digest(key.as_bytes());
+        let file_name = digest
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+
+        self.http_dir()
+            .join(provider)
+            .join(format!("{file_name}.json"))
+    }
+
+    /// Looks up a fresh entry for `key`. Always `None` when refreshing.
+    pub fn get(&self, provider: &str, key: &str) -> Option<Cached> {
+        if self.refresh {
+            return None;
+        }
+
+        let data = fs::read(self.entry_path(provider, key)).ok()?;
+        let entry = serde_json::from_slice::<Entry>(&data).ok()?;
+
+        if entry.expires_at <= now() {
+            return None;
+        }
+
+        Some(match entry.body {
+            Some(body) => Cached::Hit(body),
+            None => Cached::Miss,
+        })
+    }
+
+    fn put_entry(&self, provider: &str, key: &str, body: Option<&serde_json::Value>) {
+        let ttl = if body.is_some() {
+            self.ttl
+        } else {
+            self.negative_ttl
+        };
+
+        let entry = Entry {
+            provider: provider.to_string(),
+            expires_at: now() + ttl.as_secs(),
+            body: body.cloned(),
+        };
+
+        let path = self.entry_path(provider, key);
+
+        if let Err(err) = fs::create_dir_all(path.parent().unwrap()) {
+            errln!("Failed to create cache dir: {:?}", err);
+            return;
+        }
+
+        save_bytes_to_file(&serde_json::to_vec(&entry).unwrap(), &path);
+    }
+
+    pub fn put(&self, provider: &str, key: &str, body: &serde_json::Value) {
+        self.put_entry(provider, key, Some(body));
+    }
+
+    /// Records that `key` found nothing.
+    pub fn put_miss(&self, provider: &str, key: &str) {
+        self.put_entry(provider, key, None);
+    }
+
+    /// Removes expired entries, or every entry when `all` is set. Returns the
+    /// number of entries removed and kept.
+    pub fn prune(&self, all: bool) -> (usize, usize) {
+        let now = now();
+        let mut removed = 0;
+        let mut kept = 0;
+
+        for provider_dir in read_dir_paths(&self.http_dir()) {
+            for path in read_dir_paths(&provider_dir) {
+                let expired = all
+                    || fs::read(&path)
+                        .ok()
+                        .and_then(|data| serde_json::from_slice::<Entry>(&data).ok())
+                        .is_none_or(|entry| entry.expires_at <= now);
+
+                if expired && fs::remove_file(&path).is_ok() {
+                    removed += 1;
+                } else {
+                    kept += 1;
+                }
+            }
+
+            // Only succeeds once the provider dir is empty.
+            let _ = fs::remove_dir(&provider_dir);
+        }
+
+        (removed, kept)
+    }
+}
@@ -0,0 +1,356 @@
+//! Missing and incomplete tracks: gaps and duplicates in an album's track
+//! numbering, and fewer tracks or discs than the tags' totals (or the
+//! MusicBrainz release) say there should be.
+
+use std::{collections::BTreeMap, fmt, fs, path::Path};
+
+use crate::{formats, http::HttpClient, musicbrainz, raw_tags};
+
+/// Where a file sits in its album, according to its tags.
+pub struct TrackPosition {
+    pub file: String,
+    pub track: Option<u16>,
+    pub total_tracks: Option<u16>,
+    pub disc: Option<u16>,
+    pub total_discs: Option<u16>,
+    pub release_id: Option<String>,
+}
+
+/// Reads the position of the audio file at `path` from its tags.
+pub fn read_position(path: &Path, format: formats::AudioFormat) -> TrackPosition {
+    let raw_tags = raw_tags::read_raw_tags(path, format);
+    let tags = formats::read_track_tags(path, format, &raw_tags);
+
+    TrackPosition {
+        file: path.file_name().unwrap().to_string_lossy().to_string(),
+        track: tags.track_number,
+        total_tracks: tags.total_tracks,
+        disc: tags.disc_number,
+        total_discs: tags.total_discs,
+        release_id: raw_tags
+            .get("MUSICBRAINZ_ALBUMID")
+            .and_then(|id| musicbrainz::parse_mbid(id))
+            .map(str::to_string),
+    }
+}
+
+/// Reads the positions of the audio files directly inside `dir`.
+pub fn read_positions(dir: &Path) -> Vec<TrackPosition> {
+    let mut files = fs::read_dir(dir)
+        .map(|entries| entries.filter_map(|p| p.ok()).map(|p| p.path()).collect())
+        .unwrap_or_else(|_| vec![]);
+    files.sort();
+
+    files
+        .iter()
+        .filter_map(|path| Some(read_position(path, formats::detect(path)?)))
+        .collect()
+}
+
+pub enum Issue {
+    MissingTracks {
+        disc: Option<u16>,
+        missing: Vec<u16>,
+        found: usize,
+        expected: Option<usize>,
+    },
+    DuplicateTrack {
+        disc: Option<u16>,
+        track: u16,
+        files: Vec<String>,
+    },
+    MissingDiscs(Vec<u16>),
+    /// Without any track numbers, only the count can be checked.
+    FewerTracks {
+        found: usize,
+        expected: usize,
+    },
+    NoTrackNumber(Vec<String>),
+}
+
+fn join<T: ToString>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let disc_prefix = |disc: &Option<u16>| {
+            disc.map(|disc| format!("disc {disc}: "))
+                .unwrap_or_default()
+        };
+
+        match self {
+            Issue::MissingTracks {
+                disc,
+                missing,
+                found,
+                expected,
+            } => {
+                write!(f, "{}missing tracks {}", disc_prefix(disc), join(missing))?;
+                if let Some(expected) = expected {
+                    write!(f, " ({found} of {expected} tracks)")?;
+                }
+                Ok(())
+            }
+            Issue::DuplicateTrack { disc, track, files } => write!(
+                f,
+                "{}track {track} appears {} times ({})",
+                disc_prefix(disc),
+                files.len(),
+                join(files)
+            ),
+            Issue::MissingDiscs(discs) => write!(f, "missing discs {}", join(discs)),
+            Issue::FewerTracks { found, expected } => {
+                write!(f, "{found} of {expected} tracks")
+            }
+            Issue::NoTrackNumber(files) => write!(f, "no track number: {}", join(files)),
+        }
+    }
+}
+
+/// Checks an album's track numbering. `expected` is the number of tracks on
+/// each disc, used where the tags have no totals.
+pub fn check(positions: &[TrackPosition], expected: Option<&[usize]>) -> Vec<Issue> {
+    let mut issues = vec![];
+    let mut discs = BTreeMap::<u16, Vec<&TrackPosition>>::new();
+    let mut unnumbered = vec![];
+
+    for position in positions {
+        match position.track {
+            Some(_) => discs
+                .entry(position.disc.unwrap_or(1))
+                .or_default()
+                .push(position),
+            None => unnumbered.push(position.file.clone()),
+        }
+    }
+
+    let total_discs = positions
+        .iter()
+        .filter_map(|position| position.total_discs)
+        .max()
+        .or_else(|| expected.map(|expected| expected.len() as u16));
+    let multi_disc = discs.len() > 1 || total_discs.is_some_and(|total| total > 1);
+
+    for (disc, tracks) in &discs {
+        let disc_label = multi_disc.then_some(*disc);
+
+        let mut numbers = BTreeMap::<u16, Vec<String>>::new();
+        for track in tracks {
+            numbers
+                .entry(track.track.unwrap())
+                .or_default()
+                .push(track.file.clone());
+        }
+
+        for (track, files) in &numbers {
+            if files.len() > 1 {
+                issues.push(Issue::DuplicateTrack {
+                    disc: disc_label,
+                    track: *track,
+                    files: files.clone(),
+                });
+            }
+        }
+
+        let total = tracks
+            .iter()
+            .filter_map(|track| track.total_tracks)
+            .max()
+            .map(usize::from)
+            .or_else(|| expected?.get(usize::from(*disc).checked_sub(1)?).copied());
+        let last = numbers
+            .keys()
+            .copied()
+            .max()
+            .unwrap_or(0)
+            .max(total.unwrap_or(0) as u16);
+        let missing = (1..=last)
+            .filter(|track| !numbers.contains_key(track))
+            .collect::<Vec<_>>();
+
+        if !missing.is_empty() {
+            issues.push(Issue::MissingTracks {
+                disc: disc_label,
+                missing,
+                found: numbers.len(),
+                expected: total,
+            });
+        }
+    }
+
+    if let Some(total_discs) = total_discs.filter(|_| !discs.is_empty()) {
+        let missing = (1..=total_discs)
+            .filter(|disc| !discs.contains_key(disc))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            issues.push(Issue::MissingDiscs(missing));
+        }
+    }
+
+    if discs.is_empty() {
+        if let Some(expected) = expected.map(|expected| expected.iter().sum::<usize>()) {
+            if positions.len() < expected {
+                issues.push(Issue::FewerTracks {
+                    found: positions.len(),
+                    expected,
+                });
+            }
+        }
+    }
+
+    if !unnumbered.is_empty() {
+        issues.push(Issue::NoTrackNumber(unnumbered));
+    }
+
+    issues
+}
+
+/// The track counts of the album's MusicBrainz release, looked up only when
+/// the tags don't say how many tracks there should be.
+pub async fn expected_track_counts(
+    client: &HttpClient,
+    positions: &[TrackPosition],
+) -> Option<Vec<usize>> {
+    if positions
+        .iter()
+        .any(|position| position.total_tracks.is_some())
+    {
+        return None;
+    }
+
+    let release_id = positions
+        .iter()
+        .find_map(|position| position.release_id.as_deref())?;
+
+    musicbrainz::fetch_track_counts(client, release_id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(disc: Option<u16>, track: Option<u16>, total_tracks: Option<u16>) -> TrackPosition {
+        TrackPosition {
+            file: format!("{}-{}.flac", disc.unwrap_or(0), track.unwrap_or(0)),
+            track,
+            total_tracks,
+            disc,
+            total_discs: None,
+            release_id: None,
+        }
+    }
+
+    fn issues(positions: &[TrackPosition], expected: Option<&[usize]>) -> Vec<String> {
+        check(positions, expected)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn complete_album_has_no_issues() {
+        let positions = [
+            position(None, Some(1), Some(3)),
+            position(None, Some(2), Some(3)),
+            position(None, Some(3), Some(3)),
+        ];
+
+        assert!(issues(&positions, None).is_empty());
+    }
+
+    #[test]
+    fn finds_gaps_and_missing_trailing_tracks() {
+        let positions = [
+            position(None, Some(1), Some(5)),
+            position(None, Some(3), Some(5)),
+        ];
+
+        assert_eq!(
+            issues(&positions, None),
+            ["missing tracks 2, 4, 5 (2 of 5 tracks)"]
+        );
+    }
+
+    #[test]
+    fn finds_gaps_without_totals() {
+        let positions = [position(None, Some(1), None), position(None, Some(3), None)];
+
+        assert_eq!(issues(&positions, None), ["missing tracks 2"]);
+    }
+
+    #[test]
+    fn finds_duplicates() {
+        let mut duplicate = position(None, Some(2), None);
+        duplicate.file = "2 (copy).flac".to_string();
+        let positions = [
+            position(None, Some(1), None),
+            position(None, Some(2), None),
+            duplicate,
+        ];
+
+        assert_eq!(
+            issues(&positions, None),
+            ["track 2 appears 2 times (0-2.flac, 2 (copy).flac)"]
+        );
+    }
+
+    #[test]
+    fn checks_each_disc_separately() {
+        let positions = [
+            position(Some(1), Some(1), Some(2)),
+            position(Some(1), Some(2), Some(2)),
+            position(Some(2), Some(1), Some(3)),
+            position(Some(2), Some(3), Some(3)),
+        ];
+
+        assert_eq!(
+            issues(&positions, None),
+            ["disc 2: missing tracks 2 (2 of 3 tracks)"]
+        );
+    }
+
+    #[test]
+    fn finds_missing_discs() {
+        let mut positions = [
+            position(Some(1), Some(1), Some(1)),
+            position(Some(3), Some(1), Some(1)),
+        ];
+        for position in &mut positions {
+            position.total_discs = Some(4);
+        }
+
+        assert_eq!(issues(&positions, None), ["missing discs 2, 4"]);
+    }
+
+    #[test]
+    fn falls_back_to_the_expected_track_counts() {
+        let positions = [
+            position(Some(1), Some(1), None),
+            position(Some(1), Some(2), None),
+            position(Some(2), Some(1), None),
+        ];
+
+        assert_eq!(
+            issues(&positions, Some(&[2, 2, 1])),
+            [
+                "disc 2: missing tracks 2 (1 of 2 tracks)",
+                "missing discs 3"
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_unnumbered_tracks() {
+        let positions = [position(None, None, None), position(None, None, None)];
+
+        assert_eq!(
+            issues(&positions, Some(&[3])),
+            ["2 of 3 tracks", "no track number: 0-0.flac, 0-0.flac"]
+        );
+    }
+}
@@ -0,0 +1,199 @@
+//! The configuration file: defaults for the options cron jobs and teammates
+//! would otherwise repeat on every command line.
+//!
+//! It's read from `--config` (or `$MOOSICBOX_ORGANIZER_CONFIG`), else from
+//! `$XDG_CONFIG_HOME/moosicbox_organizer/config.toml` if that exists. Options
+//! given on the command line or through their `MOOSICBOX_ORGANIZER_*`
+//! environment variable take precedence over it.
+//!
+//! ```toml
+//! covers = true
+//! cover_size = "1200"
+//! providers = ["musicbrainz", "discogs"]
+//! creds = "/home/me/.config/moosicbox_organizer/creds.json"
+//! link_mode = "hardlink"
+//! exclude = ["*.log", "*/Singles"]
+//!
+//! [naming]
+//! artist_dir = "{artist}"
+//! album_dir = "{year} - {album}"
+//!
+//! # The first pair is used unless --pair picks another.
+//! [[pairs]]
+//! name = "player"
+//! source = "/music/flac"
+//! target = "/mnt/player/Music"
+//! ```
+
+use clap::{parser::ValueSource, ArgMatches, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{journal::LinkMode, naming, Args};
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub covers: Option<bool>,
+    pub cover_size: Option<String>,
+    pub providers: Option<Vec<String>>,
+    pub creds: Option<String>,
+    pub link_mode: Option<String>,
+    pub exclude: Option<Vec<String>>,
+    pub naming: NamingConfig,
+    pub pairs: Vec<Pair>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct NamingConfig {
+    pub artist_dir: Option<String>,
+    pub album_dir: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Pair {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub source: Option<String>,
+    pub target: Option<String>,
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("moosicbox_organizer").join("config.toml"))
+}
+
+pub fn load(path: &Path) -> Result<Config, String> {
+    let data = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    toml::from_str(&data).map_err(|err| err.to_string())
+}
+
+fn parse_value<T: ValueEnum>(key: &str, value: &str) -> Result<T, String> {
+    T::from_str(value, true).map_err(|_| {
+        let expected = T::value_variants()
+            .iter()
+            .filter_map(|variant| variant.to_possible_value())
+            .map(|value| value.get_name().to_string())
+            .collect::<Vec<_>>();
+        format!(
+            "invalid {key} '{value}', expected one of {}",
+            expected.join(", ")
+        )
+    })
+}
+
+fn value_name<T: ValueEnum>(value: &T) -> String {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
+impl Config {
+    /// Fills in the options that weren't given on the command line or
+    /// through the environment.
+    pub fn apply(&self, args: &mut Args, matches: &ArgMatches) -> Result<(), String> {
+        let unset = |id: &str| {
+            matches!(
+                matches.value_source(id),
+                None | Some(ValueSource::DefaultValue)
+            )
+        };
+
+        let pair = match &args.pair {
+            Some(name) => Some(
+                self.pairs
+                    .iter()
+                    .find(|pair| pair.name.as_deref() == Some(name.as_str()))
+                    .ok_or_else(|| format!("no pair named '{name}'"))?,
+            ),
+            None => self.pairs.first(),
+        };
+        if let Some(pair) = pair {
+            if unset("source") && pair.source.is_some() {
+                args.source = pair.source.clone();
+            }
+            if unset("target") && pair.target.is_some() {
+                args.target = pair.target.clone();
+            }
+        }
+
+        if let Some(covers) = self.covers.filter(|_| unset("covers")) {
+            args.covers = covers;
+        }
+        if let Some(cover_size) = self.cover_size.as_ref().filter(|_| unset("cover_size")) {
+            args.cover_size = parse_value("cover_size", cover_size)?;
+        }
+        if let Some(providers) = self.providers.as_ref().filter(|_| unset("providers")) {
+            args.providers = providers
+                .iter()
+                .map(|provider| parse_value("provider", provider))
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(creds) = self.creds.as_ref().filter(|_| unset("creds")) {
+            args.creds = Some(creds.clone());
+        }
+        if let Some(link_mode) = self.link_mode.as_ref().filter(|_| unset("link_mode")) {
+            args.link_mode = parse_value("link_mode", link_mode)?;
+        }
+        if let Some(exclude) = self.exclude.as_ref().filter(|_| unset("exclude")) {
+            args.exclude = exclude.clone();
+        }
+        if let Some(template) = self.naming.artist_dir.as_ref() {
+            if unset("artist_dir_template") {
+                args.artist_dir_template = template.clone();
+            }
+        }
+        if let Some(template) = self.naming.album_dir.as_ref() {
+            if unset("album_dir_template") {
+                args.album_dir_template = template.clone();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The configuration in effect, as a config file would spell it.
+    pub fn effective(args: &Args) -> Self {
+        Self {
+            covers: Some(args.covers),
+            cover_size: Some(value_name(&args.cover_size)),
+            providers: Some(args.providers.iter().map(value_name).collect()),
+            creds: args.creds.clone(),
+            link_mode: Some(value_name(&args.link_mode)),
+            exclude: Some(args.exclude.clone()),
+            naming: NamingConfig {
+                artist_dir: Some(args.artist_dir_template.clone()),
+                album_dir: Some(args.album_dir_template.clone()),
+            },
+            pairs: vec![Pair {
+                name: args.pair.clone(),
+                source: args.source.clone(),
+                target: args.target.clone(),
+            }],
+        }
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).unwrap()
+    }
+}
+
+/// Checks what can't be checked while parsing the command line, because the
+/// config file may set it.
+pub fn validate(args: &Args) -> Result<(), String> {
+    if args.mirror && args.target.is_none() {
+        return Err("--mirror requires --target, or a config file pair with a target".to_string());
+    }
+
+    if cfg!(not(unix)) && args.link_mode == LinkMode::Symlink {
+        return Err("--link-mode symlink is only supported on Unix".to_string());
+    }
+
+    naming::validate(&args.artist_dir_template)?;
+    naming::validate(&args.album_dir_template)
+}
@@ -0,0 +1,93 @@
+//! Cover Art Archive image selection for a MusicBrainz release or release
+//! group.
+
+use serde::Deserialize;
+use std::{collections::HashMap, path::Path};
+
+use crate::{
+    artwork::{download_image, CoverSize},
+    http::HttpClient,
+};
+
+pub const COVER_ART_ARCHIVE_URL: &str = "https://coverartarchive.org";
+
+/// `thumbnails` keys to try, in order, before falling back to the original
+/// image. CAA's older `small`/`large` keys alias 250/500.
+fn thumbnail_keys(size: CoverSize) -> &'static [&'static str] {
+    match size {
+        CoverSize::Small => &["500", "large"],
+        CoverSize::Large => &["1200", "500", "large"],
+        CoverSize::Original => &[],
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct CoverArt {
+    #[serde(default)]
+    images: Vec<Image>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Image {
+    image: String,
+    #[serde(default)]
+    front: bool,
+    #[serde(default)]
+    approved: bool,
+    #[serde(default)]
+    thumbnails: HashMap<String, String>,
+}
+
+impl Image {
+    fn url(&self, size: CoverSize) -> &str {
+        thumbnail_keys(size)
+            .iter()
+            .find_map(|key| self.thumbnails.get(*key))
+            .unwrap_or(&self.image)
+    }
+}
+
+/// Picks the approved front cover, falling back to an unapproved front cover.
+/// Back covers, booklet scans etc. are never selected.
+fn select_front_image(images: &[Image]) -> Option<&Image> {
+    images
+        .iter()
+        .find(|image| image.front && image.approved)
+        .or_else(|| {
+            let image = images.iter().find(|image| image.front)?;
+            outln!("Using unapproved front cover {}", image.image);
+            Some(image)
+        })
+}
+
+/// Downloads the front cover listed at `request_url` (a CAA `/release/<mbid>`
+/// or `/release-group/<mbid>` URL) into `album_path`. Returns whether a cover
+/// was saved.
+pub async fn fetch_front_cover(
+    client: &HttpClient,
+    request_url: &str,
+    size: CoverSize,
+    album_path: &Path,
+) -> bool {
+    let Some(cover_art) = client
+        .get_json::<CoverArt>("coverartarchive", client.get(request_url))
+        .await
+    else {
+        return false;
+    };
+
+    let Some(image) = select_front_image(&cover_art.images) else {
+        debugln!("No front cover listed at {request_url}");
+        return false;
+    };
+
+    download_image(
+        client,
+        "coverartarchive",
+        image.url(size),
+        album_path,
+        "cover",
+    )
+    .await
+    .is_some()
+}
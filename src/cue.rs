@@ -0,0 +1,481 @@
+//! CUE sheets, for albums ripped as one audio image plus a `.cue` file.
+//!
+//! The sheet fills in album metadata missing from the image's tags, and with
+//! `--split-cue` the image is cut into one file per track (with `ffmpeg`)
+//! before the album is copied to the target.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{exclude::Exclude, formats::AudioFormat};
+
+#[derive(Debug)]
+pub struct CueTrack {
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    /// The audio file the track is in, relative to the sheet.
+    pub file: String,
+    /// Start of `INDEX 01`, in CD frames (1/75 s).
+    pub start: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct CueSheet {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub date: Option<String>,
+    pub genre: Option<String>,
+    pub tracks: Vec<CueTrack>,
+}
+
+/// Splits a CUE line into its command and arguments, honoring quotes.
+fn tokenize(line: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+
+    for c in line.trim().chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+
+    tokens
+}
+
+/// Parses an `mm:ss:ff` timestamp into CD frames.
+fn parse_timestamp(value: &str) -> Option<u64> {
+    let mut parts = value.split(':').map(|part| part.parse::<u64>().ok());
+    let (Some(Some(minutes)), Some(Some(seconds)), Some(Some(frames))) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+
+    Some((minutes * 60 + seconds) * 75 + frames)
+}
+
+pub fn parse(text: &str) -> CueSheet {
+    let mut sheet = CueSheet::default();
+    let mut file = None;
+
+    for line in text.trim_start_matches('\u{feff}').lines() {
+        let tokens = tokenize(line);
+        let Some(command) = tokens.first() else {
+            continue;
+        };
+        let argument = tokens.get(1).cloned();
+        let track = sheet.tracks.last_mut();
+
+        match (command.to_uppercase().as_str(), track) {
+            ("FILE", _) => file = argument,
+            ("TRACK", _) => {
+                if let (Some(number), Some(file)) = (argument, &file) {
+                    sheet.tracks.push(CueTrack {
+                        number: number.parse().unwrap_or(sheet.tracks.len() as u32 + 1),
+                        title: None,
+                        performer: None,
+                        file: file.clone(),
+                        start: 0,
+                    });
+                }
+            }
+            ("TITLE", Some(track)) => track.title = argument,
+            ("TITLE", None) => sheet.title = argument,
+            ("PERFORMER", Some(track)) => track.performer = argument,
+            ("PERFORMER", None) => sheet.performer = argument,
+            ("INDEX", Some(track)) if argument.as_deref() == Some("01") => {
+                track.start = tokens
+                    .get(2)
+                    .and_then(|timestamp| parse_timestamp(timestamp))
+                    .unwrap_or(0);
+            }
+            ("REM", None) => match argument.as_deref().map(str::to_uppercase).as_deref() {
+                Some("DATE") => sheet.date = tokens.get(2).cloned(),
+                Some("GENRE") => sheet.genre = tokens.get(2).cloned(),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    sheet
+}
+
+/// Reads the first CUE sheet in `dir`, if there is one.
+pub fn find_cue_sheet(dir: &Path) -> Option<(PathBuf, CueSheet)> {
+    let path = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|p| p.ok())
+        .map(|p| p.path())
+        .find(|path| {
+            path.extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("cue"))
+        })?;
+
+    let data = fs::read(&path).ok()?;
+    let sheet = parse(&String::from_utf8_lossy(&data));
+
+    (!sheet.tracks.is_empty()).then_some((path, sheet))
+}
+
+/// Replaces characters that aren't allowed in file names.
+fn sanitize_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+impl CueSheet {
+    /// The audio files holding more than one track, which split mode cuts up.
+    pub fn images(&self) -> Vec<&str> {
+        let mut images = self
+            .tracks
+            .iter()
+            .map(|track| track.file.as_str())
+            .collect::<Vec<_>>();
+        images.dedup();
+        images
+            .into_iter()
+            .filter(|image| {
+                self.tracks
+                    .iter()
+                    .filter(|track| track.file == *image)
+                    .count()
+                    > 1
+            })
+            .collect()
+    }
+
+    /// Lossless images are split into FLAC, anything else keeps its codec.
+    fn split_extension(image: &str) -> String {
+        match AudioFormat::from_extension(Path::new(image)) {
+            Some(
+                AudioFormat::Flac
+                | AudioFormat::Wav
+                | AudioFormat::Aiff
+                | AudioFormat::WavPack
+                | AudioFormat::Ape,
+            )
+            | None => "flac".to_string(),
+            Some(_) => Path::new(image)
+                .extension()
+                .unwrap()
+                .to_string_lossy()
+                .to_lowercase(),
+        }
+    }
+
+    fn split_file_name(track: &CueTrack) -> String {
+        format!(
+            "{:02} - {}.{}",
+            track.number,
+            sanitize_file_name(track.title.as_deref().unwrap_or("Track")),
+            Self::split_extension(&track.file)
+        )
+    }
+
+    /// Names of the files split mode produces.
+    pub fn split_file_names(&self) -> Vec<String> {
+        let images = self.images();
+        self.tracks
+            .iter()
+            .filter(|track| images.contains(&track.file.as_str()))
+            .map(Self::split_file_name)
+            .collect()
+    }
+
+    fn split_track(
+        &self,
+        image: &Path,
+        track: &CueTrack,
+        end: Option<u64>,
+        target: &Path,
+    ) -> io::Result<()> {
+        let seconds = |frames: u64| format!("{:.6}", frames as f64 / 75.0);
+        let total_tracks = self.tracks.len().to_string();
+
+        let mut command = Command::new("ffmpeg");
+        command
+            .args(["-nostdin", "-v", "error", "-y", "-i"])
+            .arg(image)
+            .args(["-ss", &seconds(track.start)]);
+        if let Some(end) = end {
+            command.args(["-to", &seconds(end)]);
+        }
+        command.args(["-map", "0:a:0", "-map_metadata", "-1"]);
+        if Self::split_extension(&track.file) != "flac" {
+            command.args(["-c:a", "copy"]);
+        }
+
+        let metadata = [
+            ("title", track.title.as_deref()),
+            (
+                "artist",
+                track.performer.as_deref().or(self.performer.as_deref()),
+            ),
+            ("album_artist", self.performer.as_deref()),
+            ("album", self.title.as_deref()),
+            ("date", self.date.as_deref()),
+            ("genre", self.genre.as_deref()),
+            ("track", Some(&format!("{}/{total_tracks}", track.number))),
+        ];
+        for (key, value) in metadata {
+            if let Some(value) = value {
+                command.arg("-metadata").arg(format!("{key}={value}"));
+            }
+        }
+
+        let status = command.arg(target).status().map_err(|err| {
+            if err.kind() == io::ErrorKind::NotFound {
+                io::Error::new(
+                    err.kind(),
+                    "ffmpeg not found: install it or drop --split-cue",
+                )
+            } else {
+                err
+            }
+        })?;
+
+        if !status.success() {
+            return Err(io::Error::other(format!("ffmpeg exited with {status}")));
+        }
+
+        Ok(())
+    }
+
+    /// Fills `work_dir` with what the target should get for the album in
+    /// `source`: its images cut into tracks plus every other file except the
+    /// images, the sheet itself and what `exclude` matches. Tracks `album_dir`
+    /// already has, written since their image and the sheet last changed,
+    /// aren't split again. Returns how many tracks were split.
+    pub fn prepare_split(
+        &self,
+        source: &Path,
+        cue_path: &Path,
+        album_dir: &Path,
+        work_dir: &Path,
+        exclude: &Exclude,
+    ) -> io::Result<usize> {
+        if work_dir.exists() {
+            fs::remove_dir_all(work_dir)?;
+        }
+        fs::create_dir_all(work_dir)?;
+
+        let images = self.images();
+
+        for entry in fs::read_dir(source)?.filter_map(|p| p.ok()) {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+
+            if !path.is_file()
+                || path == cue_path
+                || images.contains(&name.as_str())
+                || exclude.matches(&path)
+            {
+                continue;
+            }
+
+            let target = work_dir.join(&name);
+            if fs::hard_link(&path, &target).is_err() {
+                fs::copy(&path, &target)?;
+                // Keeps the copy from looking newer than the target's.
+                fs::File::options()
+                    .write(true)
+                    .open(&target)?
+                    .set_modified(entry.metadata()?.modified()?)?;
+            }
+        }
+
+        let mut split = 0;
+
+        for (i, track) in self.tracks.iter().enumerate() {
+            if !images.contains(&track.file.as_str())
+                || exclude.matches(&source.join(&track.file))
+                || written_after(
+                    &album_dir.join(Self::split_file_name(track)),
+                    &[&source.join(&track.file), cue_path],
+                )
+            {
+                continue;
+            }
+
+            let end = self
+                .tracks
+                .get(i + 1)
+                .filter(|next| next.file == track.file)
+                .map(|next| next.start);
+
+            self.split_track(
+                &source.join(&track.file),
+                track,
+                end,
+                &work_dir.join(Self::split_file_name(track)),
+            )?;
+            split += 1;
+        }
+
+        Ok(split)
+    }
+}
+
+/// Whether `target` exists and was modified after every one of `sources`.
+fn written_after(target: &Path, sources: &[&Path]) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified());
+
+    let Ok(target_modified) = modified(target) else {
+        return false;
+    };
+
+    sources
+        .iter()
+        .all(|source| modified(source).is_ok_and(|modified| modified <= target_modified))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHEET: &str = r#"REM GENRE Rock
+REM DATE 1994
+PERFORMER "Some Band"
+TITLE "Some Album"
+FILE "Some Album.flac" WAVE
+  TRACK 01 AUDIO
+    TITLE "Intro"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE "What/Now?"
+    PERFORMER "Guest"
+    INDEX 00 03:58:10
+    INDEX 01 04:00:20
+FILE "Bonus.mp3" MP3
+  TRACK 03 AUDIO
+    INDEX 01 00:00:00
+"#;
+
+    #[test]
+    fn parses_timestamps_into_frames() {
+        assert_eq!(parse_timestamp("00:00:00"), Some(0));
+        assert_eq!(parse_timestamp("01:02:03"), Some((60 + 2) * 75 + 3));
+        assert_eq!(parse_timestamp("74:59:74"), Some((74 * 60 + 59) * 75 + 74));
+        assert_eq!(parse_timestamp("01:02"), None);
+        assert_eq!(parse_timestamp("aa:02:03"), None);
+    }
+
+    #[test]
+    fn parses_album_and_track_metadata() {
+        let sheet = parse(SHEET);
+
+        assert_eq!(sheet.title.as_deref(), Some("Some Album"));
+        assert_eq!(sheet.performer.as_deref(), Some("Some Band"));
+        assert_eq!(sheet.date.as_deref(), Some("1994"));
+        assert_eq!(sheet.genre.as_deref(), Some("Rock"));
+
+        let tracks = &sheet.tracks;
+        assert_eq!(tracks.len(), 3);
+        assert_eq!(tracks[0].number, 1);
+        assert_eq!(tracks[0].title.as_deref(), Some("Intro"));
+        assert_eq!(tracks[0].performer, None);
+        assert_eq!(tracks[0].file, "Some Album.flac");
+        assert_eq!(tracks[1].performer.as_deref(), Some("Guest"));
+        assert_eq!(tracks[2].file, "Bonus.mp3");
+    }
+
+    #[test]
+    fn starts_tracks_at_index_01_not_the_pregap() {
+        let sheet = parse(SHEET);
+
+        assert_eq!(sheet.tracks[0].start, 0);
+        assert_eq!(sheet.tracks[1].start, (4 * 60) * 75 + 20);
+    }
+
+    #[test]
+    fn ignores_tracks_before_any_file() {
+        let sheet = parse("TRACK 01 AUDIO\n  INDEX 01 00:00:00\n");
+
+        assert!(sheet.tracks.is_empty());
+    }
+
+    #[test]
+    fn only_splits_images_holding_several_tracks() {
+        let sheet = parse(SHEET);
+
+        assert_eq!(sheet.images(), ["Some Album.flac"]);
+        assert_eq!(
+            sheet.split_file_names(),
+            ["01 - Intro.flac", "02 - What_Now_.flac"]
+        );
+    }
+
+    #[test]
+    fn excluded_files_stay_out_of_the_split_album() {
+        let dir = tempfile::tempdir().unwrap();
+        let (source, album_dir, work_dir) = (
+            dir.path().join("source/Album"),
+            dir.path().join("target/Artist/Album"),
+            dir.path().join("split"),
+        );
+        fs::create_dir_all(&source).unwrap();
+        fs::create_dir_all(&album_dir).unwrap();
+
+        let cue_path = source.join("Some Album.cue");
+        for (name, contents) in [
+            ("Some Album.cue", SHEET),
+            ("Some Album.flac", ""),
+            ("Bonus.mp3", ""),
+            ("cover.jpg", ""),
+            ("rip.log", ""),
+        ] {
+            fs::write(source.join(name), contents).unwrap();
+        }
+        // Already split on an earlier run, so ffmpeg isn't needed.
+        let sheet = parse(SHEET);
+        for name in sheet.split_file_names() {
+            fs::write(album_dir.join(name), "").unwrap();
+        }
+
+        let exclude = Exclude::new(&dir.path().join("source"), &["*.log".to_string()]).unwrap();
+        let split = sheet
+            .prepare_split(&source, &cue_path, &album_dir, &work_dir, &exclude)
+            .unwrap();
+
+        assert_eq!(split, 0);
+        let mut names = fs::read_dir(&work_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["Bonus.mp3", "cover.jpg"]);
+    }
+
+    #[test]
+    fn keeps_the_codec_of_lossy_images() {
+        let sheet = parse(
+            "FILE \"Live.mp3\" MP3\n  TRACK 01 AUDIO\n    INDEX 01 00:00:00\n  TRACK 02 AUDIO\n    INDEX 01 01:00:00\n",
+        );
+
+        assert_eq!(
+            sheet.split_file_names(),
+            ["01 - Track.mp3", "02 - Track.mp3"]
+        );
+    }
+}
@@ -0,0 +1,98 @@
+//! Deezer public album search, used as a credential-free cover provider.
+
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::{
+    artwork::{best_match, download_image, AlbumQuery, CoverSize},
+    http::HttpClient,
+};
+
+const SEARCH_URL: &str = "https://api.deezer.com/search/album";
+const COVER_URL: &str = "https://e-cdns-images.dzcdn.net/images/cover";
+
+/// Largest size the Deezer CDN serves covers at.
+const MAX_COVER_PIXELS: u32 = 1800;
+
+#[derive(Deserialize, Debug)]
+struct SearchResponse {
+    #[serde(default)]
+    data: Vec<Album>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Album {
+    id: u64,
+    title: String,
+    artist: Artist,
+    nb_tracks: Option<u32>,
+    md5_image: Option<String>,
+    cover_xl: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Artist {
+    name: String,
+}
+
+impl Album {
+    /// The CDN renders covers at any square size from the image hash.
+    fn cover_url(&self, size: CoverSize) -> Option<String> {
+        let pixels = size.pixels().unwrap_or(MAX_COVER_PIXELS);
+
+        match &self.md5_image {
+            Some(md5_image) => Some(format!(
+                "{COVER_URL}/{md5_image}/{pixels}x{pixels}-000000-80-0-0.jpg"
+            )),
+            None => self.cover_xl.clone(),
+        }
+    }
+}
+
+/// Searches Deezer for the album and saves the best match's cover into
+/// `album_path`. Returns whether a cover was saved.
+pub async fn fetch_album_cover(
+    client: &HttpClient,
+    query: &AlbumQuery<'_>,
+    cover_size: CoverSize,
+    album_path: &Path,
+) -> bool {
+    let search_query = format!(
+        "artist:\"{}\" album:\"{}\"",
+        query.artist.replace('"', ""),
+        query.album.replace('"', "")
+    );
+    debugln!("Searching Deezer albums for {search_query}");
+
+    let request = client
+        .get(SEARCH_URL)
+        .query(&[("q", search_query.as_str()), ("limit", "25")]);
+
+    let Some(search) = client
+        .get_json::<SearchResponse>("deezer", request.try_clone().unwrap())
+        .await
+    else {
+        return false;
+    };
+
+    let Some(album) = best_match(
+        client,
+        "deezer",
+        &request,
+        "Deezer album",
+        &search.data,
+        |album| query.match_score(&album.artist.name, &album.title, album.nb_tracks),
+        |album| format!("{} '{}' by '{}'", album.id, album.title, album.artist.name),
+    ) else {
+        return false;
+    };
+
+    let Some(cover_url) = album.cover_url(cover_size) else {
+        outln!("No cover on Deezer album {}", album.id);
+        return false;
+    };
+
+    download_image(client, "deezer", &cover_url, album_path, "cover")
+        .await
+        .is_some()
+}
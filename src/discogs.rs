@@ -0,0 +1,272 @@
+//! Discogs artwork provider, authenticated with a personal access token.
+//!
+//! Discogs allows 60 authenticated requests per minute in a moving window and
+//! reports the remaining budget in the `X-Discogs-Ratelimit-Remaining` header,
+//! so requests are paused whenever that budget runs out.
+
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use std::{
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    artwork::{best_match, download_image, AlbumQuery},
+    http::{HttpClient, RateLimiter},
+    matching::similarity,
+};
+
+pub const DEFAULT_API_URL: &str = "https://api.discogs.com";
+
+/// Length of Discogs' moving rate-limit window.
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize, Debug)]
+struct SearchResponse {
+    #[serde(default)]
+    results: Vec<SearchResult>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchResult {
+    id: u64,
+    title: String,
+    catno: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Release {
+    #[serde(default)]
+    images: Vec<Image>,
+    #[serde(default)]
+    artists: Vec<ArtistRef>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ArtistRef {
+    id: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Artist {
+    #[serde(default)]
+    images: Vec<Image>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Image {
+    #[serde(rename = "type")]
+    kind: String,
+    uri: String,
+}
+
+/// Picks the image Discogs flags as primary, falling back to the first one.
+fn primary_image(images: &[Image]) -> Option<&Image> {
+    images
+        .iter()
+        .find(|image| image.kind == "primary")
+        .or(images.first())
+}
+
+/// What the last response said about the rate limit.
+struct RateLimitState {
+    remaining: Option<u32>,
+    updated: Instant,
+}
+
+/// Discogs' rate limit, which [`HttpClient`] paces its requests by.
+pub struct RateLimit(Mutex<RateLimitState>);
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self(Mutex::new(RateLimitState {
+            remaining: None,
+            updated: Instant::now(),
+        }))
+    }
+}
+
+impl RateLimiter for RateLimit {
+    fn delay(&self) -> Option<Duration> {
+        let state = self.0.lock().unwrap();
+
+        match state.remaining {
+            Some(0) => RATE_LIMIT_WINDOW.checked_sub(state.updated.elapsed()),
+            _ => None,
+        }
+    }
+
+    fn update(&self, response: &Response) {
+        let remaining = if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            // Discogs doesn't say when the window frees up, so wait it out.
+            Some(0)
+        } else {
+            response
+                .headers()
+                .get("X-Discogs-Ratelimit-Remaining")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok())
+        };
+
+        // Image downloads don't report it.
+        if remaining.is_some() {
+            let mut state = self.0.lock().unwrap();
+            state.remaining = remaining;
+            state.updated = Instant::now();
+        }
+    }
+}
+
+pub struct Discogs {
+    api_url: String,
+    token: String,
+}
+
+impl SearchResult {
+    /// Discogs titles search results as `Artist - Album`.
+    fn match_score(&self, query: &AlbumQuery) -> f64 {
+        let (artist, album) = self
+            .title
+            .split_once(" - ")
+            .unwrap_or(("", self.title.as_str()));
+
+        let catno_score = match (self.catno.as_deref(), query.catno) {
+            (Some(a), Some(b)) if similarity(a, b) == 1.0 => 1.0,
+            (Some(_), Some(_)) => 0.0,
+            _ => 0.5,
+        };
+
+        similarity(artist, query.artist) * 0.4
+            + similarity(album, query.album) * 0.5
+            + catno_score * 0.1
+    }
+}
+
+impl Discogs {
+    pub fn new(api_url: &str, token: String) -> Self {
+        Self {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            token,
+        }
+    }
+
+    fn request(&self, client: &HttpClient, path: &str, query: &[(&str, &str)]) -> RequestBuilder {
+        client
+            .get(format!("{}{path}", self.api_url))
+            .header("Authorization", format!("Discogs token={}", self.token))
+            .query(query)
+    }
+
+    /// Searches by artist and title, narrowed down by catalog number when the
+    /// tags have one. Returns the results along with the request that found
+    /// them.
+    async fn search(
+        &self,
+        client: &HttpClient,
+        query: &AlbumQuery<'_>,
+    ) -> Option<(Vec<SearchResult>, RequestBuilder)> {
+        let mut params = vec![
+            ("type", "release"),
+            ("artist", query.artist),
+            ("release_title", query.album),
+            ("per_page", "25"),
+        ];
+
+        if let Some(catno) = query.catno {
+            params.push(("catno", catno));
+            let request = self.request(client, "/database/search", &params);
+            let response = client
+                .get_json::<SearchResponse>("discogs", request.try_clone().unwrap())
+                .await?;
+            if !response.results.is_empty() {
+                return Some((response.results, request));
+            }
+            debugln!("No Discogs results for catalog number {catno}, retrying without it");
+            params.pop();
+        }
+
+        let request = self.request(client, "/database/search", &params);
+        let response = client
+            .get_json::<SearchResponse>("discogs", request.try_clone().unwrap())
+            .await?;
+
+        Some((response.results, request))
+    }
+
+    /// Searches Discogs for the album and saves the release's primary image
+    /// as the album cover and, when `fetch_artist` is set, the artist's
+    /// primary image as the artist picture. Returns whether anything was saved.
+    pub async fn fetch_album_artwork(
+        &self,
+        client: &HttpClient,
+        query: &AlbumQuery<'_>,
+        fetch_album: bool,
+        fetch_artist: bool,
+        album_path: &Path,
+    ) -> bool {
+        let Some((results, request)) = self.search(client, query).await else {
+            return false;
+        };
+
+        let Some(result) = best_match(
+            client,
+            "discogs",
+            &request,
+            "Discogs release",
+            &results,
+            |result| result.match_score(query),
+            |result| format!("{} '{}'", result.id, result.title),
+        ) else {
+            return false;
+        };
+
+        let Some(release) = client
+            .get_json::<Release>(
+                "discogs",
+                self.request(client, &format!("/releases/{}", result.id), &[]),
+            )
+            .await
+        else {
+            return false;
+        };
+
+        let mut saved = false;
+
+        if fetch_album {
+            match primary_image(&release.images) {
+                Some(image) => {
+                    saved |= download_image(client, "discogs", &image.uri, album_path, "cover")
+                        .await
+                        .is_some();
+                }
+                None => debugln!("No images on Discogs release {}", result.id),
+            }
+        }
+
+        if fetch_artist {
+            if let Some(artist_ref) = release.artists.first() {
+                if let Some(artist) = client
+                    .get_json::<Artist>(
+                        "discogs",
+                        self.request(client, &format!("/artists/{}", artist_ref.id), &[]),
+                    )
+                    .await
+                {
+                    match primary_image(&artist.images) {
+                        Some(image) => {
+                            saved |=
+                                download_image(client, "discogs", &image.uri, album_path, "artist")
+                                    .await
+                                    .is_some();
+                        }
+                        None => debugln!("No images on Discogs artist {}", artist_ref.id),
+                    }
+                }
+            }
+        }
+
+        saved
+    }
+}
@@ -0,0 +1,398 @@
+//! Duplicate albums: the same release imported more than once under
+//! different directory names ("Album", "Album (2011 Remaster)",
+//! "Album [FLAC]").
+//!
+//! Candidates share an artist and an album title once bracketed qualifiers
+//! are stripped, and are confirmed by track count, track durations and,
+//! optionally, a Chromaprint fingerprint of the first track (`fpcalc`).
+
+use clap::ValueEnum;
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+use crate::{
+    formats, quality,
+    quality::{AlbumQuality, TrackQuality},
+    raw_tags,
+};
+
+/// Tracks whose durations differ by more than this aren't the same recording.
+const DURATION_TOLERANCE: Duration = Duration::from_secs(2);
+
+/// Share of matching fingerprint bits above which two tracks are the same.
+const FINGERPRINT_THRESHOLD: f64 = 0.85;
+
+/// What to do when an album being imported duplicates one in the target.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DupePolicy {
+    /// Report the duplicate and import anyway
+    Warn,
+    /// Don't import the album
+    Skip,
+    /// Import the album only if it's of higher quality, replacing the others
+    KeepHigherQuality,
+    /// Import the album into a directory with a numbered suffix
+    KeepBoth,
+}
+
+#[derive(Clone, Copy)]
+pub struct DupeOptions {
+    pub policy: DupePolicy,
+    pub fingerprint: bool,
+}
+
+/// An album directory, as far as duplicate detection is concerned.
+pub struct AlbumCopy {
+    pub dir: PathBuf,
+    key: (String, String),
+    tracks: Vec<PathBuf>,
+    pub quality: AlbumQuality,
+}
+
+/// Lowercases `name` and drops bracketed qualifiers, punctuation and a
+/// leading "the".
+pub fn normalize(name: &str) -> String {
+    let mut stripped = String::new();
+    let mut depth = 0u32;
+
+    for c in name.chars() {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            // An unmatched closer would otherwise swallow the rest of the name.
+            ')' | ']' | '}' => depth = depth.saturating_sub(1),
+            c if depth == 0 => stripped.push(c),
+            _ => {}
+        }
+    }
+
+    let normalized = stripped
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    normalized
+        .strip_prefix("the ")
+        .map(str::to_string)
+        .unwrap_or(normalized)
+}
+
+/// Reads the album in `dir`. The artist and title come from the first
+/// track's tags, falling back to the directory names.
+pub fn read_album(dir: &Path) -> Option<AlbumCopy> {
+    let mut tracks = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|p| p.ok())
+        .map(|p| p.path())
+        .filter_map(|path| Some((formats::detect(&path)?, path)))
+        .collect::<Vec<_>>();
+    tracks.sort_by(|(_, a), (_, b)| a.cmp(b));
+
+    let (format, first) = tracks.first()?;
+    let raw_tags = raw_tags::read_raw_tags(first, *format);
+    let tags = formats::read_track_tags(first, *format, &raw_tags);
+
+    let dir_name = |dir: &Path| {
+        dir.file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
+    let album = tags.album.unwrap_or_else(|| dir_name(dir));
+    let artist = tags
+        .album_artist
+        .or(tags.artist)
+        .unwrap_or_else(|| dir.parent().map(dir_name).unwrap_or_default());
+
+    Some(AlbumCopy {
+        dir: dir.to_path_buf(),
+        key: (normalize(&artist), normalize(&album)),
+        quality: AlbumQuality {
+            tracks: tracks
+                .iter()
+                .map(|(format, path)| quality::inspect(path, *format))
+                .collect(),
+        },
+        tracks: tracks.into_iter().map(|(_, path)| path).collect(),
+    })
+}
+
+fn durations_match(a: &[TrackQuality], b: &[TrackQuality]) -> bool {
+    a.iter()
+        .zip(b)
+        .all(|(a, b)| match (a.duration, b.duration) {
+            (Some(a), Some(b)) => a.abs_diff(b) <= DURATION_TOLERANCE,
+            _ => true,
+        })
+}
+
+/// Runs `fpcalc` on the file at `path`.
+fn fingerprint(path: &Path) -> io::Result<Vec<u32>> {
+    let output = Command::new("fpcalc")
+        .args(["-raw", "-length", "120"])
+        .arg(path)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => io::Error::new(
+                err.kind(),
+                "fpcalc not found: install Chromaprint or drop --fingerprint",
+            ),
+            _ => err,
+        })?;
+
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "fpcalc exited with {}",
+            output.status
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("FINGERPRINT="))
+        .map(|values| {
+            values
+                .split(',')
+                .filter_map(|value| value.parse().ok())
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+fn fingerprints_match(a: &Path, b: &Path) -> bool {
+    let (a, b) = match (fingerprint(a), fingerprint(b)) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(err), _) | (_, Err(err)) => {
+            errln!("Failed to fingerprint: {err}");
+            // Unknown, so don't rule the duplicate out.
+            return true;
+        }
+    };
+
+    let len = a.len().min(b.len());
+    if len == 0 {
+        return true;
+    }
+
+    let matching_bits = a
+        .iter()
+        .zip(&b)
+        .map(|(a, b)| 32 - (a ^ b).count_ones())
+        .sum::<u32>();
+
+    f64::from(matching_bits) / (len * 32) as f64 >= FINGERPRINT_THRESHOLD
+}
+
+impl AlbumCopy {
+    /// Whether `other` is the same release as this album.
+    pub fn duplicates(&self, other: &AlbumCopy, fingerprint: bool) -> bool {
+        self.key == other.key
+            && self.tracks.len() == other.tracks.len()
+            && durations_match(&self.quality.tracks, &other.quality.tracks)
+            && (!fingerprint || fingerprints_match(&self.tracks[0], &other.tracks[0]))
+    }
+}
+
+/// Groups the album directories under `target_dir` (`<artist>/<album>`)
+/// that duplicate each other.
+pub fn find_in_target(target_dir: &Path, fingerprint: bool) -> Vec<Vec<AlbumCopy>> {
+    let mut candidates = HashMap::<(String, String), Vec<AlbumCopy>>::new();
+
+    for artist_dir in fs::read_dir(target_dir)
+        .map(|entries| entries.filter_map(|p| p.ok()).map(|p| p.path()).collect())
+        .unwrap_or_else(|_| vec![])
+        .into_iter()
+        .filter(|path| path.is_dir() && !is_hidden(path))
+    {
+        for album in fs::read_dir(&artist_dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|p| p.ok())
+            .map(|p| p.path())
+            .filter(|path| path.is_dir() && !is_hidden(path))
+            .filter_map(|dir| read_album(&dir))
+        {
+            candidates.entry(album.key.clone()).or_default().push(album);
+        }
+    }
+
+    let mut groups = vec![];
+
+    for (_, mut albums) in candidates {
+        while let Some(album) = albums.pop() {
+            let (same, rest) = albums
+                .into_iter()
+                .partition::<Vec<_>, _>(|other| album.duplicates(other, fingerprint));
+            albums = rest;
+
+            if !same.is_empty() {
+                let mut group = vec![album];
+                group.extend(same);
+                group.sort_by_key(|album| std::cmp::Reverse(album.quality.rank()));
+                groups.push(group);
+            }
+        }
+    }
+
+    groups.sort_by(|a, b| a[0].dir.cmp(&b[0].dir));
+    groups
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+/// The albums in `artist_dir`, other than `except`, that `album` duplicates.
+fn find_for_import(
+    album: &AlbumCopy,
+    artist_dir: &Path,
+    except: &Path,
+    fingerprint: bool,
+) -> Vec<AlbumCopy> {
+    fs::read_dir(artist_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|p| p.ok())
+        .map(|p| p.path())
+        .filter(|path| path.is_dir() && !is_hidden(path) && path != except)
+        .filter_map(|dir| read_album(&dir))
+        .filter(|existing| album.duplicates(existing, fingerprint))
+        .collect()
+}
+
+/// How an album being imported should be placed.
+pub enum Resolution {
+    /// Copy it into `dir_name` under the artist dir, then remove `replace`.
+    Import {
+        dir_name: String,
+        replace: Vec<PathBuf>,
+    },
+    Skip,
+}
+
+/// Checks the album at `source`, about to be imported as a new
+/// `<artist_dir>/<dir_name>`, against the albums already in `artist_dir`.
+pub fn resolve_import(
+    source: &Path,
+    artist_dir: &Path,
+    dir_name: &str,
+    options: &DupeOptions,
+) -> Resolution {
+    let import = |dir_name: String, replace| Resolution::Import { dir_name, replace };
+
+    let Some(album) = read_album(source) else {
+        return import(dir_name.to_string(), vec![]);
+    };
+    let dupes = find_for_import(
+        &album,
+        artist_dir,
+        &artist_dir.join(dir_name),
+        options.fingerprint,
+    );
+
+    if dupes.is_empty() {
+        return import(dir_name.to_string(), vec![]);
+    }
+
+    warnln!(
+        "Duplicate of {} ({}):",
+        source.to_str().unwrap(),
+        album.quality
+    );
+    for dupe in &dupes {
+        outln!("\t{} ({})", dupe.dir.to_str().unwrap(), dupe.quality);
+    }
+
+    match options.policy {
+        DupePolicy::Warn => import(dir_name.to_string(), vec![]),
+        DupePolicy::Skip => {
+            outln!("Skipping duplicate album");
+            Resolution::Skip
+        }
+        DupePolicy::KeepHigherQuality => {
+            if dupes
+                .iter()
+                .all(|dupe| album.quality.rank() > dupe.quality.rank())
+            {
+                outln!("Replacing lower-quality copies");
+                import(
+                    dir_name.to_string(),
+                    dupes.into_iter().map(|dupe| dupe.dir).collect(),
+                )
+            } else {
+                outln!("Skipping: the existing copy is of equal or higher quality");
+                Resolution::Skip
+            }
+        }
+        DupePolicy::KeepBoth => {
+            let dir_name = (2..)
+                .map(|n| format!("{dir_name} ({n})"))
+                .find(|name| !artist_dir.join(name).exists())
+                .unwrap();
+            outln!("Keeping both, importing as {dir_name}");
+            import(dir_name, vec![])
+        }
+    }
+}
+
+/// Removes the duplicate album directory `dir`, moving it into `backup_dir`
+/// instead when one is given.
+pub fn remove_album_dir(dir: &Path, backup_dir: Option<&Path>) -> io::Result<()> {
+    match backup_dir {
+        Some(backup_dir) => {
+            fs::create_dir_all(backup_dir)?;
+            let backup = backup_dir.join(dir.file_name().unwrap());
+            if fs::rename(dir, &backup).is_ok() {
+                return Ok(());
+            }
+            // Across filesystems: copy, then remove.
+            copy_dir(dir, &backup)?;
+            fs::remove_dir_all(dir)
+        }
+        None => fs::remove_dir_all(dir),
+    }
+}
+
+fn copy_dir(source: &Path, target: &Path) -> io::Result<()> {
+    fs::create_dir_all(target)?;
+
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let target = target.join(entry.file_name());
+
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_bracketed_qualifiers_punctuation_and_the() {
+        assert_eq!(normalize("The Wall (2011 Remaster) [FLAC]"), "wall");
+        assert_eq!(normalize("Rock'n'Roll {Deluxe}"), "rock n roll");
+        assert_eq!(normalize("Theatre"), "theatre");
+    }
+
+    #[test]
+    fn unmatched_closing_brackets_keep_the_rest_of_the_name() {
+        assert_eq!(normalize("Live) Album"), "live album");
+        assert_ne!(normalize("Live) Album"), normalize("Live) Other"));
+        assert_eq!(normalize("Live] (Disc 1) Album"), "live album");
+    }
+}
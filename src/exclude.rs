@@ -0,0 +1,96 @@
+//! `--exclude` globs: album directories and files under the source that are
+//! left alone.
+//!
+//! Patterns are matched against paths relative to the source, and `*` also
+//! matches `/`, so `*.log` excludes log files at any depth and `*/Singles`
+//! every artist's "Singles" directory.
+
+use glob::Pattern;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default)]
+pub struct Exclude {
+    source_dir: PathBuf,
+    patterns: Vec<Pattern>,
+}
+
+impl Exclude {
+    pub fn new(source_dir: &Path, patterns: &[String]) -> Result<Self, glob::PatternError> {
+        Ok(Self {
+            source_dir: source_dir.to_path_buf(),
+            patterns: patterns
+                .iter()
+                .map(|pattern| Pattern::new(pattern))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Whether `path`, under the source, is excluded, itself or through one
+    /// of its parent directories. Paths elsewhere never are.
+    pub fn matches(&self, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.source_dir) else {
+            return false;
+        };
+
+        relative
+            .ancestors()
+            .filter(|path| !path.as_os_str().is_empty())
+            .any(|path| {
+                self.patterns
+                    .iter()
+                    .any(|pattern| pattern.matches_path(path))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exclude(patterns: &[&str]) -> Exclude {
+        let patterns = patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        Exclude::new(Path::new("/music"), &patterns).unwrap()
+    }
+
+    #[test]
+    fn star_matches_at_any_depth() {
+        let exclude = exclude(&["*.log"]);
+
+        assert!(exclude.matches(Path::new("/music/rip.log")));
+        assert!(exclude.matches(Path::new("/music/Artist/Album/rip.log")));
+        assert!(!exclude.matches(Path::new("/music/Artist/Album/01.flac")));
+    }
+
+    #[test]
+    fn excludes_everything_under_a_matching_directory() {
+        let exclude = exclude(&["*/Singles"]);
+
+        assert!(exclude.matches(Path::new("/music/Artist/Singles")));
+        assert!(exclude.matches(Path::new("/music/Artist/Singles/01.flac")));
+        assert!(!exclude.matches(Path::new("/music/Artist/Album/01.flac")));
+        assert!(!exclude.matches(Path::new("/music/Singles")));
+    }
+
+    #[test]
+    fn matches_paths_relative_to_the_source() {
+        let exclude = exclude(&["Artist/Album"]);
+
+        assert!(exclude.matches(Path::new("/music/Artist/Album/01.flac")));
+        assert!(!exclude.matches(Path::new("/other/Artist/Album/01.flac")));
+        assert!(!exclude.matches(Path::new("Artist/Album/01.flac")));
+    }
+
+    #[test]
+    fn nothing_is_excluded_without_patterns() {
+        let exclude = exclude(&[]);
+
+        assert!(!exclude.matches(Path::new("/music/Artist/Album")));
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        let patterns = ["[".to_string()];
+
+        assert!(Exclude::new(Path::new("/music"), &patterns).is_err());
+    }
+}
@@ -0,0 +1,120 @@
+//! fanart.tv artist artwork, looked up by MusicBrainz artist ID.
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::{artwork::download_image, contains_file_with_prefix, http::HttpClient};
+
+pub const DEFAULT_API_URL: &str = "https://webservice.fanart.tv/v3";
+
+/// Kinds of artist artwork that can be downloaded into the artist directory.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArtworkKind {
+    Thumb,
+    Backdrop,
+    Logo,
+    Banner,
+}
+
+impl ArtworkKind {
+    /// File name (without extension) the artwork is saved as.
+    fn file_stem(self) -> &'static str {
+        match self {
+            ArtworkKind::Thumb => "artist",
+            ArtworkKind::Backdrop => "backdrop",
+            ArtworkKind::Logo => "logo",
+            ArtworkKind::Banner => "banner",
+        }
+    }
+
+    /// Response fields holding this kind of artwork, most preferred first.
+    fn fields(self) -> &'static [&'static str] {
+        match self {
+            ArtworkKind::Thumb => &["artistthumb"],
+            ArtworkKind::Backdrop => &["artistbackground"],
+            ArtworkKind::Logo => &["hdmusiclogo", "musiclogo"],
+            ArtworkKind::Banner => &["musicbanner"],
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Image {
+    url: String,
+    #[serde(default)]
+    likes: String,
+}
+
+pub struct FanartTv {
+    api_url: String,
+    api_key: String,
+    kinds: Vec<ArtworkKind>,
+}
+
+impl FanartTv {
+    pub fn new(api_url: &str, api_key: String, kinds: Vec<ArtworkKind>) -> Self {
+        Self {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            api_key,
+            kinds,
+        }
+    }
+
+    /// Downloads each enabled kind of artwork that `artist_dir` doesn't
+    /// already have. Returns whether anything was saved.
+    pub async fn fetch_artist_artwork(
+        &self,
+        client: &HttpClient,
+        artist_mbid: &str,
+        artist_dir: &Path,
+    ) -> bool {
+        let missing_kinds = self
+            .kinds
+            .iter()
+            .filter(|kind| {
+                !contains_file_with_prefix(artist_dir, &format!("{}.", kind.file_stem()))
+            })
+            .collect::<Vec<_>>();
+
+        if missing_kinds.is_empty() {
+            return false;
+        }
+
+        let request = client
+            .get(format!("{}/music/{artist_mbid}", self.api_url))
+            .query(&[("api_key", self.api_key.as_str())]);
+
+        let Some(resp) = client
+            .get_json::<serde_json::Value>("fanart", request)
+            .await
+        else {
+            debugln!("No fanart.tv artwork for artist {artist_mbid}");
+            return false;
+        };
+
+        let mut saved = false;
+
+        for kind in missing_kinds {
+            let image = kind.fields().iter().find_map(|field| {
+                let images =
+                    serde_json::from_value::<Vec<Image>>(resp.get(*field)?.clone()).ok()?;
+                images
+                    .into_iter()
+                    .max_by_key(|image| image.likes.parse::<u32>().unwrap_or_default())
+            });
+
+            match image {
+                Some(image) => {
+                    saved |=
+                        download_image(client, "fanart", &image.url, artist_dir, kind.file_stem())
+                            .await
+                            .is_some();
+                }
+                None => debugln!("No fanart.tv {:?} for artist {artist_mbid}", kind),
+            }
+        }
+
+        saved
+    }
+}
@@ -0,0 +1,243 @@
+//! Registry of the audio formats the organizer recognizes.
+//!
+//! A file counts as audio when its extension (matched whole and
+//! case-insensitively) belongs to a known format and its leading bytes look
+//! like that format. Tags are read with `audiotags` where it supports the
+//! format and from the raw tags everywhere else.
+
+use audiotags::Tag;
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::{binary::id3v2_size, raw_tags::RawTags};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudioFormat {
+    Flac,
+    Mp3,
+    /// AAC or ALAC in an MP4 container.
+    Mp4,
+    Vorbis,
+    Opus,
+    Wav,
+    Aiff,
+    WavPack,
+    Ape,
+    Dsf,
+    Dff,
+}
+
+const EXTENSIONS: &[(AudioFormat, &[&str])] = &[
+    (AudioFormat::Flac, &["flac"]),
+    (AudioFormat::Mp3, &["mp3"]),
+    (AudioFormat::Mp4, &["m4a", "mp4", "m4b", "alac"]),
+    (AudioFormat::Vorbis, &["ogg", "oga"]),
+    (AudioFormat::Opus, &["opus"]),
+    (AudioFormat::Wav, &["wav"]),
+    (AudioFormat::Aiff, &["aiff", "aif", "aifc"]),
+    (AudioFormat::WavPack, &["wv"]),
+    (AudioFormat::Ape, &["ape"]),
+    (AudioFormat::Dsf, &["dsf"]),
+    (AudioFormat::Dff, &["dff"]),
+];
+
+impl AudioFormat {
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?;
+
+        EXTENSIONS
+            .iter()
+            .find(|(_, extensions)| {
+                extensions
+                    .iter()
+                    .any(|known| known.eq_ignore_ascii_case(extension))
+            })
+            .map(|(format, _)| *format)
+    }
+
+    /// Identifies the format from the start of the file (after any ID3v2
+    /// tag).
+    fn sniff(header: &[u8], after_id3: bool) -> Option<Self> {
+        let starts_with = |offset: usize, magic: &[u8]| {
+            header
+                .get(offset..offset + magic.len())
+                .is_some_and(|bytes| bytes == magic)
+        };
+
+        Some(if starts_with(0, b"fLaC") {
+            AudioFormat::Flac
+        } else if starts_with(4, b"ftyp") {
+            AudioFormat::Mp4
+        } else if starts_with(0, b"OggS") && starts_with(28, b"OpusHead") {
+            AudioFormat::Opus
+        } else if starts_with(0, b"OggS") && starts_with(28, b"\x01vorbis") {
+            AudioFormat::Vorbis
+        } else if (starts_with(0, b"RIFF") || starts_with(0, b"RF64")) && starts_with(8, b"WAVE") {
+            AudioFormat::Wav
+        } else if starts_with(0, b"FORM") && (starts_with(8, b"AIFF") || starts_with(8, b"AIFC")) {
+            AudioFormat::Aiff
+        } else if starts_with(0, b"wvpk") {
+            AudioFormat::WavPack
+        } else if starts_with(0, b"MAC ") {
+            AudioFormat::Ape
+        } else if starts_with(0, b"DSD ") {
+            AudioFormat::Dsf
+        } else if starts_with(0, b"FRM8") && starts_with(12, b"DSD ") {
+            AudioFormat::Dff
+        } else if header.len() >= 2 && header[0] == 0xff && header[1] & 0xe0 == 0xe0 {
+            AudioFormat::Mp3
+        } else if after_id3 {
+            // MP3s commonly pad after their ID3v2 tag, so a tag alone is
+            // enough.
+            AudioFormat::Mp3
+        } else {
+            return None;
+        })
+    }
+
+    /// Whether `audiotags` can read this format's tags.
+    fn audiotags_supported(self) -> bool {
+        matches!(
+            self,
+            AudioFormat::Flac | AudioFormat::Mp3 | AudioFormat::Mp4
+        )
+    }
+}
+
+/// Reads the start of the file at `path`, skipping a leading ID3v2 tag.
+/// Returns the bytes and whether a tag was skipped.
+fn read_header(path: &Path) -> std::io::Result<(Vec<u8>, bool)> {
+    let mut file = File::open(path)?;
+    let mut header = vec![0u8; 64];
+    let len = file.read(&mut header)?;
+    header.truncate(len);
+
+    if header.len() < 10 || &header[..3] != b"ID3" {
+        return Ok((header, false));
+    }
+
+    file.seek(SeekFrom::Start(10 + id3v2_size(&header)))?;
+
+    let mut header = vec![0u8; 64];
+    let len = file.read(&mut header)?;
+    header.truncate(len);
+
+    Ok((header, true))
+}
+
+/// The format of the audio file at `path`, or `None` if it isn't one.
+pub fn detect(path: &Path) -> Option<AudioFormat> {
+    let expected = AudioFormat::from_extension(path)?;
+    let (header, after_id3) = read_header(path).ok()?;
+
+    match AudioFormat::sniff(&header, after_id3) {
+        Some(format) if format != expected => {
+            warnln!(
+                "{} is {:?}, not {:?} as its extension says",
+                path.to_str().unwrap(),
+                format,
+                expected
+            );
+            Some(format)
+        }
+        Some(format) => Some(format),
+        None => {
+            warnln!(
+                "Skipping {}: not a valid {:?} file",
+                path.to_str().unwrap(),
+                expected
+            );
+            None
+        }
+    }
+}
+
+/// The tags the organizer places and matches albums by.
+#[derive(Default, Debug)]
+pub struct TrackTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album_artist: Option<String>,
+    pub album: Option<String>,
+    pub year: Option<i32>,
+    pub description: Option<String>,
+    pub comment: Option<String>,
+    pub track_number: Option<u16>,
+    pub total_tracks: Option<u16>,
+    pub disc_number: Option<u16>,
+    pub total_discs: Option<u16>,
+}
+
+/// Parses a position tag such as `7` or `7/12` into the number and total.
+fn parse_position(value: Option<String>) -> (Option<u16>, Option<u16>) {
+    let Some(value) = value else {
+        return (None, None);
+    };
+    let (number, total) = value.split_once('/').unwrap_or((&value, ""));
+
+    (number.trim().parse().ok(), total.trim().parse().ok())
+}
+
+impl TrackTags {
+    fn from_raw_tags(raw_tags: &RawTags) -> Self {
+        let get = |keys: &[&str]| {
+            keys.iter()
+                .find_map(|key| raw_tags.get(*key))
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+
+        let (track_number, track_total) = parse_position(get(&["TRACKNUMBER"]));
+        let (disc_number, disc_total) = parse_position(get(&["DISCNUMBER"]));
+        let total = |keys: &[&str]| get(keys).and_then(|total| total.parse().ok());
+
+        Self {
+            title: get(&["TITLE"]),
+            artist: get(&["ARTIST"]),
+            album_artist: get(&["ALBUMARTIST", "ALBUM ARTIST"]),
+            album: get(&["ALBUM"]),
+            year: get(&["DATE", "YEAR", "ORIGINALDATE"])
+                .and_then(|date| date.get(..4).and_then(|year| year.parse().ok())),
+            description: get(&["DESCRIPTION"]),
+            comment: get(&["COMMENT"]),
+            track_number,
+            total_tracks: track_total.or_else(|| total(&["TRACKTOTAL", "TOTALTRACKS"])),
+            disc_number,
+            total_discs: disc_total.or_else(|| total(&["DISCTOTAL", "TOTALDISCS"])),
+        }
+    }
+}
+
+/// Reads the tags of the audio file at `path`, falling back to `raw_tags`
+/// for formats (or files) `audiotags` can't read.
+pub fn read_track_tags(path: &Path, format: AudioFormat, raw_tags: &RawTags) -> TrackTags {
+    if !format.audiotags_supported() || AudioFormat::from_extension(path) != Some(format) {
+        return TrackTags::from_raw_tags(raw_tags);
+    }
+
+    match Tag::new().read_from_path(path.to_str().unwrap()) {
+        Ok(tag) => {
+            let raw = TrackTags::from_raw_tags(raw_tags);
+            TrackTags {
+                title: tag.title().map(str::to_string).or(raw.title),
+                artist: tag.artist().map(str::to_string).or(raw.artist),
+                album_artist: tag.album_artist().map(str::to_string).or(raw.album_artist),
+                album: tag.album_title().map(str::to_string).or(raw.album),
+                year: tag.year().or(raw.year),
+                description: tag.description().map(str::to_string).or(raw.description),
+                comment: tag.comment().map(str::to_string).or(raw.comment),
+                track_number: tag.track_number().or(raw.track_number),
+                total_tracks: tag.total_tracks().or(raw.total_tracks),
+                disc_number: tag.disc_number().or(raw.disc_number),
+                total_discs: tag.total_discs().or(raw.total_discs),
+            }
+        }
+        Err(err) => {
+            errln!("Failed to read tags from {}: {:?}", path.display(), err);
+            TrackTags::from_raw_tags(raw_tags)
+        }
+    }
+}
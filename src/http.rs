@@ -0,0 +1,368 @@
+//! HTTP client shared by the artwork providers, with responses cached on disk.
+//!
+//! Requests are retried with jittered exponential backoff on timeouts,
+//! connection errors, 429s and 5xx responses. A provider that keeps failing
+//! is disabled for the rest of the run by a per-provider circuit breaker, so
+//! an outage doesn't cost every remaining album a full timeout. Providers
+//! that report their rate limit in responses can also have a [`RateLimiter`]
+//! pace their requests.
+
+use rand::Rng;
+use reqwest::{header::RETRY_AFTER, Client, IntoUrl, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use tokio::sync::Semaphore;
+
+use crate::{
+    cache::{Cache, Cached},
+    progress,
+};
+
+pub struct RetryPolicy {
+    /// Retries after the first attempt.
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    /// Longest backoff between attempts. A `Retry-After` longer than this
+    /// gives up instead of waiting.
+    pub max_delay: Duration,
+    /// Consecutive failed requests after which a provider is disabled.
+    pub circuit_breaker_threshold: u32,
+}
+
+impl RetryPolicy {
+    /// Exponential backoff with "equal jitter": half the delay is fixed, the
+    /// other half random, so concurrent retries don't synchronize.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        let half = delay / 2;
+        let jitter = rand::thread_rng().gen_range(0..=half.as_millis() as u64);
+
+        half + Duration::from_millis(jitter)
+    }
+}
+
+#[derive(Default)]
+struct ProviderHealth {
+    consecutive_failures: u32,
+    total_failures: u32,
+    disabled: bool,
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Parses a `Retry-After` header, given either in seconds or as an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+
+    match value.trim().parse::<u64>() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => httpdate::parse_http_date(value)
+            .ok()?
+            .duration_since(SystemTime::now())
+            .ok(),
+    }
+}
+
+/// The full URL (including query) `request` would be sent to.
+pub fn request_url(request: &RequestBuilder) -> Option<String> {
+    let request = request.try_clone()?.build().ok()?;
+    Some(request.url().to_string())
+}
+
+/// Paces a provider's requests by what its responses say about its rate
+/// limit.
+pub trait RateLimiter: Send + Sync {
+    /// How long to wait before sending the next request, if at all.
+    fn delay(&self) -> Option<Duration>;
+
+    /// Takes note of a response.
+    fn update(&self, response: &Response);
+}
+
+/// Providers whose usage policy only allows one request at a time.
+const SERIAL_PROVIDERS: &[&str] = &["musicbrainz"];
+
+pub struct HttpClient {
+    client: Client,
+    cache: Option<Cache>,
+    retry: RetryPolicy,
+    health: Mutex<BTreeMap<String, ProviderHealth>>,
+    /// Max concurrent requests per provider.
+    provider_jobs: usize,
+    provider_permits: Mutex<HashMap<String, Arc<Semaphore>>>,
+    rate_limiters: HashMap<String, Box<dyn RateLimiter>>,
+}
+
+impl HttpClient {
+    pub fn new(
+        client: Client,
+        cache: Option<Cache>,
+        retry: RetryPolicy,
+        provider_jobs: usize,
+    ) -> Self {
+        Self {
+            client,
+            cache,
+            retry,
+            health: Mutex::new(BTreeMap::new()),
+            provider_jobs,
+            provider_permits: Mutex::new(HashMap::new()),
+            rate_limiters: HashMap::new(),
+        }
+    }
+
+    pub fn with_rate_limiter(
+        mut self,
+        provider: &str,
+        limiter: impl RateLimiter + 'static,
+    ) -> Self {
+        self.rate_limiters
+            .insert(provider.to_string(), Box::new(limiter));
+        self
+    }
+
+    fn provider_permits(&self, provider: &str) -> Arc<Semaphore> {
+        self.provider_permits
+            .lock()
+            .unwrap()
+            .entry(provider.to_string())
+            .or_insert_with(|| {
+                let permits = if SERIAL_PROVIDERS.contains(&provider) {
+                    1
+                } else {
+                    self.provider_jobs
+                };
+                Arc::new(Semaphore::new(permits))
+            })
+            .clone()
+    }
+
+    fn is_disabled(&self, provider: &str) -> bool {
+        self.health
+            .lock()
+            .unwrap()
+            .get(provider)
+            .is_some_and(|health| health.disabled)
+    }
+
+    fn record_success(&self, provider: &str) {
+        if let Some(health) = self.health.lock().unwrap().get_mut(provider) {
+            health.consecutive_failures = 0;
+        }
+    }
+
+    fn record_failure(&self, provider: &str) {
+        let mut health = self.health.lock().unwrap();
+        let health = health.entry(provider.to_string()).or_default();
+        health.consecutive_failures += 1;
+        health.total_failures += 1;
+
+        if !health.disabled && health.consecutive_failures >= self.retry.circuit_breaker_threshold {
+            health.disabled = true;
+            warnln!(
+                "Disabling {provider} for the rest of the run after {} consecutive failures",
+                health.consecutive_failures
+            );
+        }
+    }
+
+    /// Providers the circuit breaker disabled during the run, along with
+    /// their total number of failed requests.
+    pub fn disabled_providers(&self) -> Vec<(String, u32)> {
+        self.health
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, health)| health.disabled)
+            .map(|(provider, health)| (provider.clone(), health.total_failures))
+            .collect()
+    }
+
+    /// Sends an idempotent request, retrying transient failures. Returns
+    /// `None` when the request failed for good (which is logged) or the
+    /// provider has been disabled. A final 429/5xx response is returned as-is
+    /// after the retries are exhausted.
+    pub async fn send(&self, provider: &str, request: RequestBuilder) -> Option<Response> {
+        let mut attempt = 0;
+
+        loop {
+            if self.is_disabled(provider) {
+                debugln!("Skipping request to disabled provider {provider}");
+                return None;
+            }
+
+            let rate_limiter = self.rate_limiters.get(provider);
+            if let Some(delay) = rate_limiter.and_then(|limiter| limiter.delay()) {
+                outln!(
+                    "{provider} rate limit reached, waiting {}s",
+                    delay.as_secs() + 1
+                );
+                tokio::time::sleep(delay).await;
+            }
+
+            let permits = self.provider_permits(provider);
+            let permit = permits.acquire().await.unwrap();
+            let lookup = progress::lookup(provider);
+            let result = request
+                .try_clone()
+                .expect("Only requests without streaming bodies can be retried")
+                .send()
+                .await;
+            drop((lookup, permit));
+
+            if let (Some(limiter), Ok(response)) = (rate_limiter, &result) {
+                limiter.update(response);
+            }
+
+            let retry_delay = match &result {
+                Ok(response) if is_retryable_status(response.status()) => {
+                    Some(retry_after(response).unwrap_or_else(|| self.retry.backoff(attempt)))
+                }
+                Ok(_) => None,
+                Err(err) if err.is_timeout() || err.is_connect() || err.is_request() => {
+                    Some(self.retry.backoff(attempt))
+                }
+                Err(_) => None,
+            };
+
+            if let Some(delay) = retry_delay {
+                if attempt < self.retry.max_retries && delay <= self.retry.max_delay {
+                    attempt += 1;
+                    match &result {
+                        Ok(response) => warnln!(
+                            "{provider} request failed with status {}, retrying in {}ms ({attempt}/{})",
+                            response.status(),
+                            delay.as_millis(),
+                            self.retry.max_retries
+                        ),
+                        Err(err) => warnln!(
+                            "{provider} request failed ({err}), retrying in {}ms ({attempt}/{})",
+                            delay.as_millis(),
+                            self.retry.max_retries
+                        ),
+                    }
+                    tokio::time::sleep(delay).await;
+                    continue;
+                }
+            }
+
+            return match result {
+                Ok(response) => {
+                    if is_retryable_status(response.status()) {
+                        self.record_failure(provider);
+                    } else {
+                        self.record_success(provider);
+                    }
+                    Some(response)
+                }
+                Err(err) => {
+                    errln!("Failed to fetch from {provider}: {:?}", err);
+                    self.record_failure(provider);
+                    None
+                }
+            };
+        }
+    }
+
+    pub fn get(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    /// Looks up a fresh cached response for `key`.
+    pub fn cached(&self, provider: &str, key: &str) -> Option<Cached> {
+        self.cache.as_ref()?.get(provider, key)
+    }
+
+    pub fn cache(&self, provider: &str, key: &str, body: &serde_json::Value) {
+        if let Some(cache) = &self.cache {
+            cache.put(provider, key, body);
+        }
+    }
+
+    /// Records that `key` found nothing, so it's skipped on later runs.
+    pub fn cache_miss(&self, provider: &str, key: &str) {
+        if let Some(cache) = &self.cache {
+            cache.put_miss(provider, key);
+        }
+    }
+
+    /// Records that the response to `request` (e.g. a search) held no
+    /// acceptable match. This replaces its cached response with a miss, so
+    /// it's only looked up again once the negative TTL runs out.
+    pub fn cache_no_match(&self, provider: &str, request: &RequestBuilder) {
+        if let Some(request_url) = request_url(request) {
+            self.cache_miss(provider, &request_url);
+        }
+    }
+
+    /// Sends a GET and deserializes the JSON response, going through the
+    /// cache keyed by the request URL. Returns `None` on errors (which are
+    /// logged) and on 404s (which are cached as misses).
+    pub async fn get_json<T: DeserializeOwned>(
+        &self,
+        provider: &str,
+        request: RequestBuilder,
+    ) -> Option<T> {
+        let Some(request_url) = request_url(&request) else {
+            errln!("Failed to build {provider} request");
+            return None;
+        };
+
+        let body = match self.cached(provider, &request_url) {
+            Some(Cached::Hit(body)) => {
+                debugln!("Using cached response for {request_url}");
+                body
+            }
+            Some(Cached::Miss) => {
+                debugln!("Using cached miss for {request_url}");
+                return None;
+            }
+            None => {
+                debugln!("Fetching from {request_url}");
+
+                let response = self.send(provider, request).await?;
+
+                match response.status() {
+                    StatusCode::NOT_FOUND => {
+                        debugln!("Nothing found at {request_url}");
+                        self.cache_miss(provider, &request_url);
+                        return None;
+                    }
+                    status if !status.is_success() => {
+                        errln!("{provider} request {request_url} failed with status {status}");
+                        return None;
+                    }
+                    _ => {}
+                }
+
+                let body = match response.json::<serde_json::Value>().await {
+                    Ok(body) => body,
+                    Err(err) => {
+                        errln!("Deserialization failure {:?}", err);
+                        return None;
+                    }
+                };
+
+                self.cache(provider, &request_url, &body);
+                body
+            }
+        };
+
+        match serde_json::from_value(body) {
+            Ok(value) => Some(value),
+            Err(err) => {
+                errln!("Deserialization failure {:?}", err);
+                None
+            }
+        }
+    }
+}
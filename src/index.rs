@@ -0,0 +1,480 @@
+//! Embedded SQLite index of the source albums seen on previous runs.
+//!
+//! For every source album it records the files (path, size, mtime and,
+//! optionally, SHA-256), the metadata resolved from its tags, where it was
+//! placed in the target and whether it has a cover. Later runs compare the
+//! source directory against it and skip albums that haven't changed.
+
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS albums (
+    id INTEGER PRIMARY KEY,
+    source_path TEXT NOT NULL UNIQUE,
+    artist TEXT NOT NULL,
+    album TEXT NOT NULL,
+    year INTEGER,
+    musicbrainz_release_id TEXT,
+    target_path TEXT,
+    cover_status TEXT NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS files (
+    album_id INTEGER NOT NULL REFERENCES albums(id) ON DELETE CASCADE,
+    path TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    mtime INTEGER NOT NULL,
+    hash TEXT,
+    PRIMARY KEY (album_id, path)
+);
+CREATE TABLE IF NOT EXISTS target_files (
+    album_id INTEGER NOT NULL REFERENCES albums(id) ON DELETE CASCADE,
+    path TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    hash TEXT NOT NULL,
+    PRIMARY KEY (album_id, path)
+);
+";
+
+/// A file directly inside a source album directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceFile {
+    /// Path relative to the album directory.
+    pub path: String,
+    pub size: u64,
+    /// Modification time in milliseconds since the Unix epoch.
+    pub mtime: i64,
+    pub hash: Option<String>,
+}
+
+impl SourceFile {
+    fn same_contents_as(&self, other: &SourceFile) -> bool {
+        self.path == other.path && self.size == other.size && self.mtime == other.mtime
+    }
+}
+
+fn mtime_millis(metadata: &fs::Metadata) -> i64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_millis() as i64)
+}
+
+/// Lists the files (not directories) directly inside `dir`, sorted by path.
+pub fn list_source_files(dir: &Path) -> Vec<SourceFile> {
+    let mut files = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|p| p.ok())
+                .filter_map(|entry| {
+                    let metadata = entry.metadata().ok()?;
+                    metadata.is_file().then(|| SourceFile {
+                        path: entry.file_name().to_string_lossy().to_string(),
+                        size: metadata.len(),
+                        mtime: mtime_millis(&metadata),
+                        hash: None,
+                    })
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    files
+}
+
+/// Whether `files` differ from the `stored` listing of the same directory.
+pub fn files_changed(stored: &HashMap<String, SourceFile>, files: &[SourceFile]) -> bool {
+    stored.len() != files.len()
+        || files.iter().any(|file| {
+            stored
+                .get(&file.path)
+                .is_none_or(|stored| !stored.same_contents_as(file))
+        })
+}
+
+/// Fills in the hashes of `files` in `dir`: reused from `stored` when the
+/// file is unchanged, otherwise computed if `hash_new` is set.
+pub fn fill_hashes(
+    dir: &Path,
+    files: &mut [SourceFile],
+    stored: &HashMap<String, SourceFile>,
+    hash_new: bool,
+) {
+    for file in files.iter_mut() {
+        file.hash = match stored.get(&file.path) {
+            Some(stored) if stored.same_contents_as(file) && stored.hash.is_some() => {
+                stored.hash.clone()
+            }
+            _ if hash_new => hash_file(&dir.join(&file.path))
+                .map_err(|err| errln!("Failed to hash {}: {:?}", file.path, err))
+                .ok(),
+            _ => None,
+        };
+    }
+}
+
+/// SHA-256 of the file at `path`, hex encoded.
+pub fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1024 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+/// Whether the album's cover is in place.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CoverStatus {
+    Present,
+    Missing,
+}
+
+impl CoverStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CoverStatus::Present => "present",
+            CoverStatus::Missing => "missing",
+        }
+    }
+
+    fn from_str(value: &str) -> Self {
+        match value {
+            "present" => CoverStatus::Present,
+            _ => CoverStatus::Missing,
+        }
+    }
+}
+
+/// Expected contents of a file written to an album's target directory.
+#[derive(Clone, Debug)]
+pub struct ManifestEntry {
+    /// Path relative to the album's target directory.
+    pub path: String,
+    pub size: u64,
+    pub hash: String,
+}
+
+/// What a run learned about a source album.
+pub struct AlbumRecord<'a> {
+    pub source_path: &'a Path,
+    pub artist: &'a str,
+    pub album: &'a str,
+    pub year: Option<i32>,
+    pub musicbrainz_release_id: Option<&'a str>,
+    pub target_path: Option<&'a Path>,
+    pub cover_status: CoverStatus,
+    pub files: &'a [SourceFile],
+}
+
+/// An album as stored in the index.
+#[derive(Debug)]
+pub struct IndexedAlbum {
+    pub source_path: PathBuf,
+    pub artist: String,
+    pub album: String,
+    pub year: Option<i32>,
+    pub musicbrainz_release_id: Option<String>,
+    pub target_path: Option<PathBuf>,
+    pub cover_status: CoverStatus,
+    pub file_count: usize,
+    pub total_size: u64,
+}
+
+pub struct LibraryIndex {
+    conn: Mutex<Connection>,
+    /// Hash new and changed files when recording them.
+    pub hash_files: bool,
+    /// Process every album, even those unchanged since the last run.
+    pub rescan: bool,
+}
+
+/// Default index location: `$XDG_DATA_HOME/moosicbox_organizer/library.db`.
+pub fn default_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("moosicbox_organizer").join("library.db"))
+}
+
+impl LibraryIndex {
+    pub fn open(path: &Path, hash_files: bool, rescan: bool) -> rusqlite::Result<Self> {
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+
+        let conn = Connection::open(path)?;
+        conn.execute_batch("PRAGMA foreign_keys = ON; PRAGMA journal_mode = WAL;")?;
+        conn.execute_batch(SCHEMA)?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+            hash_files,
+            rescan,
+        })
+    }
+
+    fn album_id(conn: &Connection, source_path: &Path) -> rusqlite::Result<Option<i64>> {
+        conn.query_row(
+            "SELECT id FROM albums WHERE source_path = ?1",
+            params![source_path.to_string_lossy()],
+            |row| row.get(0),
+        )
+        .optional()
+    }
+
+    /// The files recorded for the album at `source_path`, keyed by path.
+    pub fn files(&self, source_path: &Path) -> HashMap<String, SourceFile> {
+        let conn = self.conn.lock().unwrap();
+
+        let Ok(Some(album_id)) = Self::album_id(&conn, source_path) else {
+            return HashMap::new();
+        };
+
+        let mut statement = conn
+            .prepare("SELECT path, size, mtime, hash FROM files WHERE album_id = ?1")
+            .unwrap();
+
+        statement
+            .query_map(params![album_id], |row| {
+                Ok(SourceFile {
+                    path: row.get(0)?,
+                    size: row.get::<_, i64>(1)? as u64,
+                    mtime: row.get(2)?,
+                    hash: row.get(3)?,
+                })
+            })
+            .unwrap()
+            .filter_map(|file| file.ok())
+            .map(|file| (file.path.clone(), file))
+            .collect()
+    }
+
+    pub fn album(&self, source_path: &Path) -> Option<IndexedAlbum> {
+        self.query_albums(
+            "WHERE a.source_path = ?1",
+            params![source_path.to_string_lossy()],
+        )
+        .pop()
+    }
+
+    /// Whether the album at `source_path` has the same files as when it was
+    /// last recorded, and that run left it complete: placed under
+    /// `target_dir`, or `lower_quality_dir` if it lost to the target's copy
+    /// (when given), and with a cover (when `needs_cover`).
+    pub fn is_unchanged(
+        &self,
+        source_path: &Path,
+        files: &[SourceFile],
+        target_dir: Option<&Path>,
+        lower_quality_dir: Option<&Path>,
+        transcode_dir: Option<&Path>,
+        needs_cover: bool,
+    ) -> bool {
+        let Some(album) = self.album(source_path) else {
+            return false;
+        };
+
+        let files_unchanged = !files_changed(&self.files(source_path), files);
+
+        // Where the album is within whichever of the two it was placed under.
+        let placed_at = album.target_path.as_deref().and_then(|target| {
+            target_dir
+                .into_iter()
+                .chain(lower_quality_dir)
+                .find_map(|dir| target.strip_prefix(dir).ok())
+        });
+        let placed = target_dir.is_none()
+            || (placed_at.is_some()
+                && album
+                    .target_path
+                    .as_ref()
+                    .is_some_and(|target| target.is_dir()));
+        // The transcoded copy mirrors the target's layout, which naming
+        // templates may have changed from `<artist>/<source dir>`.
+        let transcoded = transcode_dir.is_none_or(|transcode_dir| {
+            let relative = placed_at
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| Path::new(&album.artist).join(source_path.file_name().unwrap()));
+            transcode_dir.join(relative).is_dir()
+        });
+
+        files_unchanged
+            && placed
+            && transcoded
+            && (!needs_cover || album.cover_status == CoverStatus::Present)
+    }
+
+    pub fn record_album(&self, record: &AlbumRecord) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        let updated_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        tx.execute(
+            "INSERT INTO albums (source_path, artist, album, year, musicbrainz_release_id, target_path, cover_status, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+             ON CONFLICT (source_path) DO UPDATE SET
+                artist = excluded.artist,
+                album = excluded.album,
+                year = excluded.year,
+                musicbrainz_release_id = excluded.musicbrainz_release_id,
+                target_path = COALESCE(excluded.target_path, albums.target_path),
+                cover_status = excluded.cover_status,
+                updated_at = excluded.updated_at",
+            params![
+                record.source_path.to_string_lossy(),
+                record.artist,
+                record.album,
+                record.year,
+                record.musicbrainz_release_id,
+                record.target_path.map(|path| path.to_string_lossy()),
+                record.cover_status.as_str(),
+                updated_at,
+            ],
+        )?;
+
+        let album_id = Self::album_id(&tx, record.source_path)?.unwrap();
+
+        tx.execute("DELETE FROM files WHERE album_id = ?1", params![album_id])?;
+
+        for file in record.files {
+            tx.execute(
+                "INSERT INTO files (album_id, path, size, mtime, hash) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![album_id, file.path, file.size as i64, file.mtime, file.hash],
+            )?;
+        }
+
+        tx.commit()
+    }
+
+    /// The manifest of the target copy of the album at `source_path`.
+    pub fn manifest(&self, source_path: &Path) -> Vec<ManifestEntry> {
+        let conn = self.conn.lock().unwrap();
+
+        let Ok(Some(album_id)) = Self::album_id(&conn, source_path) else {
+            return vec![];
+        };
+
+        let mut statement = conn
+            .prepare("SELECT path, size, hash FROM target_files WHERE album_id = ?1 ORDER BY path")
+            .unwrap();
+
+        statement
+            .query_map(params![album_id], |row| {
+                Ok(ManifestEntry {
+                    path: row.get(0)?,
+                    size: row.get::<_, i64>(1)? as u64,
+                    hash: row.get(2)?,
+                })
+            })
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .collect()
+    }
+
+    /// Adds or replaces manifest entries of an already recorded album.
+    pub fn update_manifest(
+        &self,
+        source_path: &Path,
+        entries: &[ManifestEntry],
+    ) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        let Some(album_id) = Self::album_id(&tx, source_path)? else {
+            return Ok(());
+        };
+
+        for entry in entries {
+            tx.execute(
+                "INSERT OR REPLACE INTO target_files (album_id, path, size, hash) VALUES (?1, ?2, ?3, ?4)",
+                params![album_id, entry.path, entry.size as i64, entry.hash],
+            )?;
+        }
+
+        tx.commit()
+    }
+
+    /// Drops the manifest entry of a file deleted from the target.
+    pub fn remove_target_file(&self, target: &Path) -> rusqlite::Result<()> {
+        let (Some(album_dir), Some(file_name)) = (target.parent(), target.file_name()) else {
+            return Ok(());
+        };
+
+        self.conn.lock().unwrap().execute(
+            "DELETE FROM target_files
+             WHERE path = ?2 AND album_id IN (SELECT id FROM albums WHERE target_path = ?1)",
+            params![album_dir.to_string_lossy(), file_name.to_string_lossy()],
+        )?;
+        Ok(())
+    }
+
+    pub fn remove_album(&self, source_path: &Path) -> rusqlite::Result<()> {
+        self.conn.lock().unwrap().execute(
+            "DELETE FROM albums WHERE source_path = ?1",
+            params![source_path.to_string_lossy()],
+        )?;
+        Ok(())
+    }
+
+    /// Every indexed album, ordered by artist and album.
+    pub fn albums(&self) -> Vec<IndexedAlbum> {
+        self.query_albums("", [])
+    }
+
+    fn query_albums(&self, filter: &str, params: impl rusqlite::Params) -> Vec<IndexedAlbum> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut statement = conn
+            .prepare(&format!(
+                "SELECT a.source_path, a.artist, a.album, a.year, a.musicbrainz_release_id,
+                        a.target_path, a.cover_status, COUNT(f.path), COALESCE(SUM(f.size), 0)
+                 FROM albums a LEFT JOIN files f ON f.album_id = a.id
+                 {filter}
+                 GROUP BY a.id
+                 ORDER BY a.artist, a.album"
+            ))
+            .unwrap();
+
+        statement
+            .query_map(params, |row| {
+                Ok(IndexedAlbum {
+                    source_path: PathBuf::from(row.get::<_, String>(0)?),
+                    artist: row.get(1)?,
+                    album: row.get(2)?,
+                    year: row.get(3)?,
+                    musicbrainz_release_id: row.get(4)?,
+                    target_path: row.get::<_, Option<String>>(5)?.map(PathBuf::from),
+                    cover_status: CoverStatus::from_str(&row.get::<_, String>(6)?),
+                    file_count: row.get::<_, i64>(7)? as usize,
+                    total_size: row.get::<_, i64>(8)? as u64,
+                })
+            })
+            .unwrap()
+            .filter_map(|album| album.ok())
+            .collect()
+    }
+}
@@ -0,0 +1,100 @@
+//! iTunes Search API, used as a credential-free cover provider.
+
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::{
+    artwork::{best_match, download_image, AlbumQuery, CoverSize},
+    http::HttpClient,
+};
+
+const SEARCH_URL: &str = "https://itunes.apple.com/search";
+
+#[derive(Deserialize, Debug)]
+struct SearchResponse {
+    #[serde(default)]
+    results: Vec<Album>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Album {
+    collection_id: u64,
+    collection_name: String,
+    artist_name: String,
+    track_count: Option<u32>,
+    artwork_url100: Option<String>,
+}
+
+impl Album {
+    /// The artwork URL embeds its size (`.../100x100bb.jpg`), and the image
+    /// server renders whatever size is asked for. `100000x100000-999` returns
+    /// the uncompressed source image.
+    fn cover_url(&self, size: CoverSize) -> Option<String> {
+        let artwork_url = self.artwork_url100.as_ref()?;
+        let dimensions = match size.pixels() {
+            Some(pixels) => format!("{pixels}x{pixels}bb"),
+            None => "100000x100000-999".to_string(),
+        };
+
+        Some(artwork_url.replace("100x100bb", &dimensions))
+    }
+}
+
+/// Searches iTunes for the album and saves the best match's cover into
+/// `album_path`. Returns whether a cover was saved.
+pub async fn fetch_album_cover(
+    client: &HttpClient,
+    query: &AlbumQuery<'_>,
+    cover_size: CoverSize,
+    album_path: &Path,
+) -> bool {
+    let term = format!("{} {}", query.artist, query.album);
+    debugln!("Searching iTunes albums for {term}");
+
+    let request = client.get(SEARCH_URL).query(&[
+        ("term", term.as_str()),
+        ("media", "music"),
+        ("entity", "album"),
+        ("limit", "25"),
+    ]);
+
+    let Some(search) = client
+        .get_json::<SearchResponse>("itunes", request.try_clone().unwrap())
+        .await
+    else {
+        return false;
+    };
+
+    let Some(album) = best_match(
+        client,
+        "itunes",
+        &request,
+        "iTunes album",
+        &search.results,
+        |album| {
+            query.match_score(
+                &album.artist_name,
+                &album.collection_name,
+                album.track_count,
+            )
+        },
+        |album| {
+            format!(
+                "{} '{}' by '{}'",
+                album.collection_id, album.collection_name, album.artist_name
+            )
+        },
+    ) else {
+        return false;
+    };
+
+    let Some(cover_url) = album.cover_url(cover_size) else {
+        outln!("No cover on iTunes album {}", album.collection_id);
+        return false;
+    };
+
+    download_image(client, "itunes", &cover_url, album_path, "cover")
+        .await
+        .is_some()
+}
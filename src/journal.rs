@@ -0,0 +1,302 @@
+//! Crash-safe copies into the target.
+//!
+//! New albums are copied into a hidden staging directory next to their final
+//! location, fsynced and renamed into place, so an album directory in the
+//! target is either complete or absent. Changed files are written to a
+//! temporary file and renamed over the old one. Each album operation is
+//! recorded in a journal under the target before it starts and removed once
+//! it's done; entries left behind by a crash are rolled back on the next run.
+//!
+//! Files end up in the target as copies, or as hard or symbolic links to the
+//! source, depending on the [`LinkMode`].
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::exclude::Exclude;
+
+const STAGING_SUFFIX: &str = ".moosicbox-staging";
+const TEMP_SUFFIX: &str = ".moosicbox-tmp";
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Operation {
+    /// Copying a new album into `staging_dir`, to be renamed to `album_dir`.
+    CopyAlbum {
+        source: PathBuf,
+        staging_dir: PathBuf,
+        album_dir: PathBuf,
+    },
+    /// Replacing or adding files in the existing `album_dir`.
+    UpdateAlbum { source: PathBuf, album_dir: PathBuf },
+}
+
+/// How files are put into the target.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LinkMode {
+    #[default]
+    Copy,
+    /// Hard link to the source file, falling back to a copy across filesystems
+    Hardlink,
+    /// Symbolic link to the source file's absolute path (Unix only)
+    Symlink,
+}
+
+#[derive(Clone)]
+pub struct Journal {
+    dir: PathBuf,
+    pub link_mode: LinkMode,
+}
+
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+/// Copies (or links) `source` to `target` and flushes it to disk.
+fn copy_file_synced(source: &Path, target: &Path, link_mode: LinkMode) -> io::Result<()> {
+    match link_mode {
+        LinkMode::Copy => {}
+        LinkMode::Hardlink => match fs::hard_link(source, target) {
+            Ok(()) => return Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+                debugln!(
+                    "Can't hard link across filesystems, copying {}",
+                    source.display()
+                );
+            }
+            Err(err) => return Err(err),
+        },
+        #[cfg(unix)]
+        LinkMode::Symlink => return std::os::unix::fs::symlink(source.canonicalize()?, target),
+        #[cfg(not(unix))]
+        LinkMode::Symlink => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "symbolic links are only supported on Unix",
+            ))
+        }
+    }
+
+    let bytes = fs::copy(source, target)?;
+    fs::File::open(target)?.sync_all()?;
+    crate::progress::add_bytes(bytes);
+    Ok(())
+}
+
+/// Recursively copies the contents of `source` into the new directory
+/// `target`, leaving out what `exclude` matches.
+fn copy_dir_synced(
+    source: &Path,
+    target: &Path,
+    link_mode: LinkMode,
+    exclude: &Exclude,
+) -> io::Result<()> {
+    fs::create_dir(target)?;
+
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let target = target.join(entry.file_name());
+
+        if exclude.matches(&entry.path()) {
+            continue;
+        }
+
+        if entry.file_type()?.is_dir() {
+            copy_dir_synced(&entry.path(), &target, link_mode, exclude)?;
+        } else {
+            copy_file_synced(&entry.path(), &target, link_mode)?;
+        }
+    }
+
+    sync_dir(target)
+}
+
+fn hidden_sibling(path: &Path, suffix: &str) -> PathBuf {
+    let name = path.file_name().unwrap().to_str().unwrap();
+    path.with_file_name(format!(".{name}{suffix}"))
+}
+
+impl Journal {
+    /// Journal kept in `<target_dir>/.moosicbox_organizer/journal`.
+    pub fn new(target_dir: &Path, link_mode: LinkMode) -> Self {
+        Self {
+            dir: target_dir.join(".moosicbox_organizer").join("journal"),
+            link_mode,
+        }
+    }
+
+    fn entry_path(&self, album_dir: &Path) -> PathBuf {
+        let digest = Sha256::digest(album_dir.to_string_lossy().as_bytes());
+        let file_name = digest
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+
+        self.dir.join(format!("{file_name}.json"))
+    }
+
+    fn begin(&self, album_dir: &Path, operation: &Operation) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+
+        let entry = self.entry_path(album_dir);
+        fs::write(&entry, serde_json::to_vec(operation).unwrap())?;
+        fs::File::open(&entry)?.sync_all()?;
+        sync_dir(&self.dir)?;
+
+        Ok(entry)
+    }
+
+    fn finish(&self, entry: &Path) {
+        if let Err(err) = fs::remove_file(entry) {
+            errln!("Failed to remove journal entry: {:?}", err);
+        }
+    }
+
+    /// Copies the album at `source` to the not yet existing `album_dir`.
+    pub fn copy_album(&self, source: &Path, album_dir: &Path, exclude: &Exclude) -> io::Result<()> {
+        let staging_dir = hidden_sibling(album_dir, STAGING_SUFFIX);
+
+        let entry = self.begin(
+            album_dir,
+            &Operation::CopyAlbum {
+                source: source.to_path_buf(),
+                staging_dir: staging_dir.clone(),
+                album_dir: album_dir.to_path_buf(),
+            },
+        )?;
+
+        if staging_dir.exists() {
+            fs::remove_dir_all(&staging_dir)?;
+        }
+
+        let copied = copy_dir_synced(source, &staging_dir, self.link_mode, exclude)
+            .and_then(|_| fs::rename(&staging_dir, album_dir))
+            .and_then(|_| sync_dir(album_dir.parent().unwrap()));
+
+        if copied.is_err() {
+            let _ = fs::remove_dir_all(&staging_dir);
+        }
+
+        self.finish(&entry);
+        copied
+    }
+
+    /// Starts updating files of the existing `album_dir`. Every file has to
+    /// be written with [`replace_file`] before calling [`Journal::end_update`].
+    pub fn begin_update(&self, source: &Path, album_dir: &Path) -> io::Result<PathBuf> {
+        self.begin(
+            album_dir,
+            &Operation::UpdateAlbum {
+                source: source.to_path_buf(),
+                album_dir: album_dir.to_path_buf(),
+            },
+        )
+    }
+
+    pub fn end_update(&self, entry: &Path, album_dir: &Path) {
+        if let Err(err) = sync_dir(album_dir) {
+            errln!("Failed to sync {}: {:?}", album_dir.to_str().unwrap(), err);
+        }
+        self.finish(entry);
+    }
+
+    /// Rolls back operations interrupted by a previous run: removes staging
+    /// directories and half-written temporary files. Returns the source
+    /// albums involved, which need to be processed again.
+    pub fn recover(&self) -> Vec<PathBuf> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return vec![];
+        };
+
+        let mut sources = vec![];
+
+        for entry in entries.filter_map(|p| p.ok()).map(|p| p.path()) {
+            let Some(operation) = fs::read(&entry)
+                .ok()
+                .and_then(|data| serde_json::from_slice::<Operation>(&data).ok())
+            else {
+                let _ = fs::remove_file(&entry);
+                continue;
+            };
+
+            match operation {
+                Operation::CopyAlbum {
+                    source,
+                    staging_dir,
+                    album_dir,
+                } => {
+                    if staging_dir.exists() {
+                        outln!(
+                            "Rolling back interrupted copy of {}",
+                            album_dir.to_str().unwrap()
+                        );
+                        if let Err(err) = fs::remove_dir_all(&staging_dir) {
+                            errln!(
+                                "Failed to remove {}: {:?}",
+                                staging_dir.to_str().unwrap(),
+                                err
+                            );
+                            continue;
+                        }
+                    }
+                    sources.push(source);
+                }
+                Operation::UpdateAlbum { source, album_dir } => {
+                    outln!(
+                        "Repairing interrupted update of {}",
+                        album_dir.to_str().unwrap()
+                    );
+                    for temp_file in fs::read_dir(&album_dir)
+                        .map(|entries| entries.filter_map(|p| p.ok()).map(|p| p.path()))
+                        .into_iter()
+                        .flatten()
+                        .filter(|path| path.to_string_lossy().ends_with(TEMP_SUFFIX))
+                    {
+                        let _ = fs::remove_file(temp_file);
+                    }
+                    sources.push(source);
+                }
+            }
+
+            let _ = fs::remove_file(&entry);
+        }
+
+        sources
+    }
+}
+
+/// Whether `target` is a hard or symbolic link to `source` rather than a copy
+/// of it, so that changing one changes the other.
+pub fn is_linked(source: &Path, target: &Path) -> bool {
+    // A dangling link is as good as missing.
+    if fs::symlink_metadata(target).is_ok_and(|metadata| metadata.is_symlink()) {
+        return target.exists();
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        if let (Ok(source), Ok(target)) = (fs::metadata(source), fs::metadata(target)) {
+            return source.dev() == target.dev() && source.ino() == target.ino();
+        }
+    }
+
+    false
+}
+
+/// Atomically replaces (or creates) `target` with a copy of `source`.
+pub fn replace_file(source: &Path, target: &Path, link_mode: LinkMode) -> io::Result<()> {
+    let temp_file = hidden_sibling(target, TEMP_SUFFIX);
+
+    copy_file_synced(source, &temp_file, link_mode)
+        .and_then(|_| fs::rename(&temp_file, target))
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&temp_file);
+        })
+}
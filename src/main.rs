@@ -1,20 +1,53 @@
-use audiotags::Tag;
-use clap::{command, Parser};
-use fs_extra::dir::CopyOptions;
-use regex::Regex;
+#[macro_use]
+mod output;
+
+mod artwork;
+mod binary;
+mod cache;
+mod completeness;
+mod config;
+mod cover_art_archive;
+mod cue;
+mod deezer;
+mod discogs;
+mod dupes;
+mod exclude;
+mod fanart;
+mod formats;
+mod http;
+mod index;
+mod itunes;
+mod journal;
+mod matching;
+mod mirror;
+mod musicbrainz;
+mod naming;
+mod progress;
+mod quality;
+mod raw_tags;
+mod report;
+mod transcode;
+mod verify;
+mod watch;
+
+use artwork::{CoverSize, Provider};
+use clap::{error::ErrorKind, CommandFactory, FromArgMatches, Parser, Subcommand};
+use futures::{FutureExt, StreamExt};
 use reqwest::{header, Client};
 use serde::Deserialize;
-use std::ops::{Bound, RangeBounds};
 use std::{
+    cmp::Ordering,
     fs::{self},
-    io::Write,
+    io::{IsTerminal, Write},
+    panic::AssertUnwindSafe,
     path::{Path, PathBuf},
+    sync::Arc,
     time::{Duration, SystemTime},
 };
+use tokio::sync::Semaphore;
 
 trait StringUtils {
     fn substring(&self, start: usize, len: usize) -> &str;
-    fn slice(&self, range: impl RangeBounds<usize>) -> &str;
 }
 
 impl StringUtils for str {
@@ -48,77 +81,198 @@ impl StringUtils for str {
         }
         &self[byte_start..byte_end]
     }
-    fn slice(&self, range: impl RangeBounds<usize>) -> &str {
-        let start = match range.start_bound() {
-            Bound::Included(bound) | Bound::Excluded(bound) => *bound,
-            Bound::Unbounded => 0,
-        };
-        let len = match range.end_bound() {
-            Bound::Included(bound) => *bound + 1,
-            Bound::Excluded(bound) => *bound,
-            Bound::Unbounded => self.len(),
-        } - start;
-        self.substring(start, len)
-    }
+}
+
+/// The entries of `dir`, or none if it can't be read.
+fn read_dir_paths(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .map(|entries| entries.filter_map(|p| p.ok()).map(|p| p.path()).collect())
+        .unwrap_or_default()
 }
 
 fn save_bytes_to_file(bytes: &[u8], path: &PathBuf) {
     let mut file = fs::OpenOptions::new()
         .create(true)
         .write(true)
+        .truncate(true)
         .open(path)
         .unwrap();
 
     let _ = file.write_all(bytes);
 }
 
+fn contains_file_with_prefix(dir: &Path, prefix: &str) -> bool {
+    fs::read_dir(dir)
+        .map(|entries| {
+            entries.filter_map(|p| p.ok()).any(|f| {
+                f.file_name()
+                    .to_str()
+                    .is_some_and(|n| n.starts_with(prefix))
+            })
+        })
+        .unwrap_or(false)
+}
+
+/// Artwork lookup settings shared by every album in a run.
+struct ArtworkOptions {
+    tidal_auth: Option<String>,
+    cover_size: CoverSize,
+    providers: Vec<Provider>,
+    discogs: Option<discogs::Discogs>,
+    fanart: Option<fanart::FanartTv>,
+}
+
 async fn copy_album_dir_contents(
     target_dir: Option<String>,
     path: PathBuf,
-    client: &Client,
+    client: &http::HttpClient,
     fetch_covers: bool,
-    tidal_auth: Option<String>,
-) -> Option<String> {
+    artwork: &ArtworkOptions,
+    sync: &SyncOptions,
+    library: Option<&index::LibraryIndex>,
+) -> Option<AlbumUpdate> {
+    let tidal_auth = &artwork.tidal_auth;
+
     let files = fs::read_dir(path.clone())
         .unwrap()
         .filter_map(|p| p.ok())
         .collect::<Vec<_>>();
 
-    let music_file_pattern = Regex::new(r".+\.(flac|m4a|mp3)").unwrap();
-    let audio_files = files
-        .iter()
-        .filter(|p| music_file_pattern.is_match(p.file_name().to_str().unwrap()))
-        .collect::<Vec<_>>();
+    let source_files = index::list_source_files(&path);
+    let indexed_files = library
+        .map(|library| library.files(&path))
+        .unwrap_or_default();
 
-    if audio_files.is_empty() {
-        println!(
+    if let Some(library) = library {
+        if !library.rescan
+            && library.is_unchanged(
+                &path,
+                &source_files,
+                target_dir.as_deref().map(Path::new),
+                sync.lower_quality_dir.as_deref(),
+                sync.transcode
+                    .as_ref()
+                    .map(|transcode| transcode.target_dir.as_path()),
+                fetch_covers,
+            )
+        {
+            outln!("Unchanged since last run: {}", path.to_str().unwrap());
+            report::record(|report| report.status = report::AlbumStatus::Unchanged);
+            return None;
+        }
+    }
+
+    // Reading headers and tags blocks on the disk, so it's done off the
+    // runtime and within the disk limit.
+    let inspected = {
+        let _permit = sync.disk.acquire().await.unwrap();
+        let source = path.clone();
+        report::spawn_blocking(move || inspect_album(&source)).await
+    };
+
+    let Some(AlbumFiles {
+        audio_files,
+        raw_tags,
+        tags,
+        cue_sheet,
+        quality: album_quality,
+        positions,
+    }) = inspected
+    else {
+        outln!(
             "Encountered empty directory {}",
             path.clone().to_str().unwrap()
         );
+        report::record(|report| report.status = report::AlbumStatus::Skipped);
         return None;
-    }
+    };
 
-    let music_file = audio_files.first().unwrap();
+    let (music_file, format) = audio_files.first().unwrap();
 
-    let tag = Tag::new()
-        .read_from_path(music_file.path().to_str().unwrap())
-        .unwrap();
+    outln!("====== {} ======", path.clone().to_str().unwrap());
+
+    if !indexed_files.is_empty() && index::files_changed(&indexed_files, &source_files) {
+        outln!("Source files changed since last run");
+    }
+
+    let cue_sheet = cue_sheet.map(|(cue_path, sheet)| {
+        outln!(
+            "CUE sheet: {} ({} tracks)",
+            cue_path.to_str().unwrap(),
+            sheet.tracks.len()
+        );
+        sheet
+    });
 
-    let title = tag.title().unwrap();
-    let album = tag.album_title().unwrap_or("(none)");
-    let artist = tag.artist().or(tag.album_artist()).unwrap();
     let album_dir_name = path.file_name().unwrap().to_str().unwrap();
+    let title = tags.title.as_deref().unwrap_or("(none)");
+    let album = tags
+        .album
+        .as_deref()
+        .or(cue_sheet.as_ref().and_then(|sheet| sheet.title.as_deref()))
+        .unwrap_or_else(|| {
+            warnln!("No album tag, falling back to the directory name");
+            album_dir_name
+        });
+    let Some(artist) = tags
+        .artist
+        .as_deref()
+        .or(tags.album_artist.as_deref())
+        .or(cue_sheet
+            .as_ref()
+            .and_then(|sheet| sheet.performer.as_deref()))
+        .or_else(|| {
+            warnln!("No artist tag, falling back to the parent directory name");
+            path.parent()
+                .and_then(|parent| parent.file_name())
+                .and_then(|name| name.to_str())
+        })
+    else {
+        errln!(
+            "Unable to determine the artist of {}",
+            path.to_str().unwrap()
+        );
+        return None;
+    };
+
+    debugln!("file: {} ({:?})", music_file.to_str().unwrap(), format);
+    let year = tags.year.or_else(|| {
+        cue_sheet
+            .as_ref()
+            .and_then(|sheet| sheet.date.as_ref())
+            .and_then(|date| date.get(..4)?.parse().ok())
+    });
 
-    println!("====== {} ======", path.clone().to_str().unwrap());
-    println!("file: {}", music_file.path().to_str().unwrap());
-    println!("title: {}", title);
-    println!("album title: {}", album);
-    println!("album directory name: {}", album_dir_name);
-    println!("artist: {}", artist);
-    println!("description: {:?}", tag.description());
-    println!("comment: {:?}", tag.comment());
+    debugln!("title: {}", title);
+    debugln!("album title: {}", album);
+    debugln!("album directory name: {}", album_dir_name);
+    debugln!("artist: {}", artist);
+    debugln!("description: {:?}", tags.description);
+    debugln!("comment: {:?}", tags.comment);
+
+    let fields = naming::Fields {
+        artist,
+        album,
+        year,
+        album_dir: album_dir_name,
+    };
+    let target_artist = sync.naming.artist_dir(&fields);
+    let target_album = sync.naming.album_dir(&fields);
+
+    outln!("quality: {}", album_quality);
+
+    let expected_tracks = if sync.musicbrainz_track_counts {
+        completeness::expected_track_counts(client, &positions).await
+    } else {
+        None
+    };
+    for issue in completeness::check(&positions, expected_tracks.as_deref()) {
+        warnln!("Incomplete album: {issue}");
+    }
 
     let mut created_new_cover = false;
+    // Where the album cover downloaded during this run came from.
+    let mut cover_provider = None;
 
     let contains_album_cover = files.iter().any(|f| {
         f.file_name()
@@ -133,35 +287,28 @@ async fn copy_album_dir_contents(
 
     if fetch_covers && (!contains_album_cover || !contains_artist_cover) {
         if let Some(tidal_auth) = &tidal_auth {
-            if let Some(description) = tag.description().or(tag.comment()) {
+            if let Some(description) = tags.description.as_deref().or(tags.comment.as_deref()) {
                 let tidal_prefix = "https://listen.tidal.com/album/";
                 if description.starts_with(tidal_prefix) {
                     let remainder = description.strip_prefix(tidal_prefix).unwrap();
                     let tidal_album_id = remainder.substring(0, remainder.find('/').unwrap());
                     let request_url = format!("https://listen.tidal.com/v1/albums/{tidal_album_id}?countryCode=US&locale=en_US&deviceType=BROWSER");
-                    println!("Fetching from {request_url}");
 
-                    if let Some(resp) = match client
-                        .get(request_url)
-                        .header("Authorization", format!("Bearer {tidal_auth}"))
-                        .send()
-                        .await
-                        .unwrap()
-                        .json::<serde_json::Value>()
+                    if let Some(resp) = client
+                        .get_json::<serde_json::Value>(
+                            "tidal",
+                            client
+                                .get(request_url)
+                                .header("Authorization", format!("Bearer {tidal_auth}")),
+                        )
                         .await
                     {
-                        Ok(resp) => Some(resp),
-                        Err(err) => {
-                            eprintln!("Deserialization failure {:?}", err);
-                            None
-                        }
-                    } {
                         if !contains_artist_cover {
                             if let Some(artist) = resp.get("artist") {
                                 if let Some(artist) = artist.as_object() {
                                     if let Some(artist_pic) = artist.get("picture") {
                                         if artist_pic.is_null() {
-                                            println!("No Artist picture associated with artist");
+                                            debugln!("No Artist picture associated with artist");
                                         }
                                         if let Some(artist_pic_path) = artist_pic.as_str() {
                                             let artist_pic_path = artist_pic_path.replace('-', "/");
@@ -169,22 +316,11 @@ async fn copy_album_dir_contents(
                                             let request_url = format!(
                                                 "https://resources.tidal.com/images/{artist_pic_path}/750x750.jpg"
                                             );
-                                            println!("Fetching from {request_url}");
+                                            debugln!("Fetching from {request_url}");
 
-                                            if let Some(resp) = match client
-                                                .get(request_url)
-                                                .send()
-                                                .await
+                                            if let Some(resp) =
+                                                client.send("tidal", client.get(request_url)).await
                                             {
-                                                Ok(resp) => Some(resp),
-                                                Err(err) => {
-                                                    eprintln!(
-                                                        "Failed to fetch tidal artist picture: {:?}",
-                                                        err
-                                                    );
-                                                    None
-                                                }
-                                            } {
                                                 match resp.bytes().await {
                                                     Ok(bytes) => {
                                                         let cover_file_path =
@@ -196,7 +332,7 @@ async fn copy_album_dir_contents(
                                                         created_new_cover = true;
                                                     }
                                                     Err(error) => {
-                                                        eprintln!(
+                                                        errln!(
                                                             "Deserialization failure {:?}",
                                                             error
                                                         )
@@ -215,26 +351,20 @@ async fn copy_album_dir_contents(
                                     let request_url = format!(
                                         "https://resources.tidal.com/images/{cover_path}/1280x1280.jpg"
                                     );
-                                    println!("Fetching from {request_url}");
-
-                                    if let Some(resp) = match client.get(request_url).send().await {
-                                        Ok(resp) => Some(resp),
-                                        Err(err) => {
-                                            eprintln!(
-                                                "Failed to fetch tidal artist album: {:?}",
-                                                err
-                                            );
-                                            None
-                                        }
-                                    } {
+                                    debugln!("Fetching from {request_url}");
+
+                                    if let Some(resp) =
+                                        client.send("tidal", client.get(request_url)).await
+                                    {
                                         match resp.bytes().await {
                                             Ok(bytes) => {
                                                 let cover_file_path = path.join("cover.jpg");
                                                 save_bytes_to_file(&bytes, &cover_file_path);
                                                 created_new_cover = true;
+                                                cover_provider = Some("tidal");
                                             }
                                             Err(error) => {
-                                                eprintln!("Deserialization failure {:?}", error)
+                                                errln!("Deserialization failure {:?}", error)
                                             }
                                         };
                                     }
@@ -247,182 +377,1082 @@ async fn copy_album_dir_contents(
         }
 
         if tidal_auth.is_some() && !created_new_cover {
-            eprintln!("Failed to fetch Tidal artist album");
-        } else if !created_new_cover {
-            let re = Regex::new(r"[^A-Za-z0-9 _]").unwrap();
-            let request_url = format!(
-                "http://musicbrainz.org/ws/2/release/?query=artist:{}%20AND%20title:{}%20AND%20packaging:None",
-                re.replace_all(artist, "").replace(' ', "%20"),
-                re.replace_all(album, "").replace(' ', "%20"),
-            );
-            println!("Fetching from {request_url}",);
-            if let Some(resp) = match client
-                .get(request_url)
-                .send()
-                .await
-                .unwrap()
-                .json::<serde_json::Value>()
-                .await
-            {
-                Ok(resp) => Some(resp),
-                Err(err) => {
-                    eprintln!("Failed to fetch artist album: {:?}", err);
-                    None
+            errln!("Failed to fetch Tidal artist album");
+        }
+
+        let query = artwork::AlbumQuery {
+            artist,
+            album,
+            track_count: cue_sheet
+                .as_ref()
+                .map_or(audio_files.len(), |sheet| sheet.tracks.len()),
+            year,
+            release_id: raw_tags
+                .get("MUSICBRAINZ_ALBUMID")
+                .and_then(|id| musicbrainz::parse_mbid(id)),
+            release_group_id: raw_tags
+                .get("MUSICBRAINZ_RELEASEGROUPID")
+                .and_then(|id| musicbrainz::parse_mbid(id)),
+            catno: raw_tags.get("CATALOGNUMBER").map(|catno| catno.as_str()),
+        };
+        let cover_size = artwork.cover_size;
+
+        for provider in &artwork.providers {
+            let fetch_album = !contains_file_with_prefix(&path, "cover.");
+            let fetch_artist = !contains_file_with_prefix(&path, "artist.");
+
+            let saved = match provider {
+                Provider::MusicBrainz if fetch_album => {
+                    musicbrainz::fetch_album_cover(client, &query, cover_size, &path).await
                 }
-            } {
-                if let Some(releases) = resp.get("releases") {
-                    if let Some(releases) = releases.as_array() {
-                        if let Some(release) = releases.first() {
-                            if let Some(id) = release.get("id") {
-                                if let Some(id) = id.as_str() {
-                                    let request_url =
-                                        format!("http://coverartarchive.org/release/{id}");
-                                    println!("Fetching {request_url}");
-                                    if let Some(resp) = match client
-                                        .get(request_url)
-                                        .send()
-                                        .await
-                                        .unwrap()
-                                        .json::<serde_json::Value>()
-                                        .await
-                                    {
-                                        Ok(resp) => Some(resp),
-                                        Err(err) => {
-                                            eprintln!("Failed to fetch artist album: {:?}", err);
-                                            None
-                                        }
-                                    } {
-                                        if let Some(images) = resp.get("images") {
-                                            if let Some(images) = images.as_array() {
-                                                if let Some(image) = images.first() {
-                                                    if let Some(main_image) = image.get("image") {
-                                                        if let Some(main_image) =
-                                                            main_image.as_str()
-                                                        {
-                                                            let ext_index =
-                                                                main_image.rfind('.').unwrap() + 1;
-                                                            let end_index = main_image.len();
-                                                            let extension = main_image
-                                                                .slice(ext_index..end_index);
-                                                            let cover_file_path = path.join(
-                                                                format!("cover.{}", extension),
-                                                            );
-                                                            if let Some(resp) = match client
-                                                                .get(main_image)
-                                                                .send()
-                                                                .await
-                                                            {
-                                                                Ok(resp) => Some(resp),
-                                                                Err(err) => {
-                                                                    eprintln!("Failed to fetch artist album: {:?}", err);
-                                                                    None
-                                                                }
-                                                            } {
-                                                                match resp.bytes().await {
-                                                                    Ok(bytes) => {
-                                                                        save_bytes_to_file(
-                                                                            &bytes,
-                                                                            &cover_file_path,
-                                                                        );
-                                                                        created_new_cover = true;
-                                                                    }
-                                                                    Err(error) => eprintln!(
-                                                                        "Deserialization failure {:?}",
-                                                                        error
-                                                                    ),
-                                                                };
-                                                            }
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
+                Provider::Deezer if fetch_album => {
+                    deezer::fetch_album_cover(client, &query, cover_size, &path).await
+                }
+                Provider::Itunes if fetch_album => {
+                    itunes::fetch_album_cover(client, &query, cover_size, &path).await
+                }
+                Provider::Discogs if fetch_album || fetch_artist => match &artwork.discogs {
+                    Some(discogs) => {
+                        discogs
+                            .fetch_album_artwork(client, &query, fetch_album, fetch_artist, &path)
+                            .await
                     }
+                    None => false,
+                },
+                _ => false,
+            };
+
+            if saved {
+                created_new_cover = true;
+                if fetch_album && contains_file_with_prefix(&path, "cover.") {
+                    cover_provider = Some(provider.name());
                 }
             }
         }
+
+        if !created_new_cover {
+            outln!("No new artwork found");
+        }
+    }
+
+    if let Some(provider) = cover_provider {
+        let dimensions = fs::read_dir(&path).ok().and_then(|entries| {
+            entries
+                .filter_map(|p| p.ok())
+                .find(|p| p.file_name().to_string_lossy().starts_with("cover."))
+                .and_then(|cover| artwork::image_dimensions(&cover.path()))
+        });
+        report::record(|report| {
+            report.cover = Some(report::CoverReport {
+                provider: provider.to_string(),
+                width: dimensions.map(|(width, _)| width),
+                height: dimensions.map(|(_, height)| height),
+            })
+        });
     }
 
-    if let Some(target_dir) = target_dir {
-        let artist_dir = Path::new(&target_dir).join(artist);
+    let mut album_target = target_dir.as_ref().map(|target_dir| {
+        Path::new(target_dir)
+            .join(&target_artist)
+            .join(&target_album)
+    });
+
+    let updated = if let Some(target_dir) = target_dir {
+        let artist_dir = Path::new(&target_dir).join(&target_artist);
 
         if !artist_dir.is_dir() {
-            println!("Creating artist dir {}", artist_dir.to_str().unwrap());
+            outln!("Creating artist dir {}", artist_dir.to_str().unwrap());
             let _ = fs::create_dir(artist_dir.clone());
         }
 
-        let album_dir = artist_dir.join(album_dir_name);
+        if fetch_covers {
+            if let Some(fanart) = &artwork.fanart {
+                let artist_mbid = raw_tags
+                    .get("MUSICBRAINZ_ALBUMARTISTID")
+                    .or(raw_tags.get("MUSICBRAINZ_ARTISTID"))
+                    .and_then(|id| musicbrainz::parse_mbid(id));
 
-        let existing_files = if album_dir.is_dir() {
-            fs::read_dir(album_dir.clone())
-                .unwrap()
-                .filter_map(|p| p.ok())
-                .collect::<Vec<_>>()
-        } else {
-            vec![]
-        };
+                match artist_mbid {
+                    Some(artist_mbid) => {
+                        fanart
+                            .fetch_artist_artwork(client, artist_mbid, &artist_dir)
+                            .await;
+                    }
+                    None => outln!("No MusicBrainz artist ID in tags, skipping fanart.tv"),
+                }
+            }
+        }
 
-        if created_new_cover || files.len() > existing_files.len() {
-            let source = path.to_str().unwrap();
-            let target = artist_dir.to_str().unwrap();
-            println!("Copying album dir {} -> {}", source, target);
-            if !album_dir.is_dir() {
-                let _ = fs_extra::dir::copy(source, target, &CopyOptions::new());
-                Some(String::from(album_dir.to_str().unwrap()))
-            } else {
-                let copied_files = fs::read_dir(path.clone())
-                    .unwrap()
-                    .filter_map(|p| p.ok())
-                    .filter_map(|source| {
-                        let target = Path::new(album_dir.to_str().unwrap())
-                            .join(source.path().file_name().unwrap().to_str().unwrap());
-
-                        if target.is_file() {
-                            None
-                        } else {
-                            Some((source.path(), target))
+        let mut album_dir = artist_dir.join(&target_album);
+        let mut replace = vec![];
+
+        // Where an earlier run put the album, which may not be `album_dir` if
+        // it was imported next to a duplicate.
+        let indexed_dir = library
+            .and_then(|library| library.album(&path))
+            .and_then(|album| album.target_path)
+            .filter(|dir| dir.parent() == Some(artist_dir.as_path()) && dir.is_dir());
+
+        if let Some(indexed_dir) = indexed_dir {
+            album_dir = indexed_dir;
+        } else if !album_dir.is_dir() {
+            if let Some(dupes) = sync.dupes {
+                let (source, target_artist_dir, dir_name) =
+                    (path.clone(), artist_dir.clone(), target_album.clone());
+                let resolution = report::spawn_blocking(move || {
+                    dupes::resolve_import(&source, &target_artist_dir, &dir_name, &dupes)
+                })
+                .await;
+
+                match resolution {
+                    dupes::Resolution::Import {
+                        dir_name,
+                        replace: lower_quality,
+                    } => {
+                        album_dir = artist_dir.join(dir_name);
+                        replace = lower_quality;
+                    }
+                    dupes::Resolution::Skip => {
+                        report::record(|report| report.status = report::AlbumStatus::Skipped);
+                        return None;
+                    }
+                }
+            }
+        }
+
+        // Files of a lower-quality copy this album replaces, removed once it's
+        // copied.
+        let mut superseded = vec![];
+
+        if sync.quality_policy != quality::QualityPolicy::Merge && album_dir.is_dir() {
+            let existing = {
+                let _permit = sync.disk.acquire().await.unwrap();
+                let album_dir = album_dir.clone();
+                report::spawn_blocking(move || quality::read_album_quality(&album_dir)).await
+            };
+
+            if !existing.tracks.is_empty() {
+                match sync.quality_policy.compare(&album_quality, &existing) {
+                    Ordering::Greater => {
+                        outln!(
+                            "Replacing the {} copy in the target with {}",
+                            existing,
+                            album_quality
+                        );
+                        let source_names = source_files
+                            .iter()
+                            .map(|file| file.path.as_str())
+                            .collect::<Vec<_>>();
+                        superseded = fs::read_dir(&album_dir)
+                            .unwrap()
+                            .filter_map(|p| p.ok())
+                            .map(|p| p.path())
+                            .filter(|file| {
+                                formats::AudioFormat::from_extension(file).is_some()
+                                    && file
+                                        .file_name()
+                                        .and_then(|name| name.to_str())
+                                        .is_some_and(|name| !source_names.contains(&name))
+                            })
+                            .collect();
+                    }
+                    Ordering::Less => {
+                        let Some(lower_quality_dir) = &sync.lower_quality_dir else {
+                            outln!(
+                                "Skipping: {} is lower quality than the {} copy in the target",
+                                album_quality,
+                                existing
+                            );
+                            report::record(|report| report.status = report::AlbumStatus::Skipped);
+                            return None;
+                        };
+                        album_dir = lower_quality_dir.join(&target_artist).join(&target_album);
+                        outln!(
+                            "{} is lower quality than the {} copy in the target, copying to {}",
+                            album_quality,
+                            existing,
+                            album_dir.to_str().unwrap()
+                        );
+                        if let Err(err) = fs::create_dir_all(album_dir.parent().unwrap()) {
+                            errln!("Failed to create lower-quality dir: {:?}", err);
+                            return None;
                         }
-                    })
-                    .map(|(source, target)| {
-                        let track_source = source.to_str().unwrap();
-                        let track_target = target.to_str().unwrap();
-                        let _ = fs::copy(track_source, track_target);
-                        format!("\t{}", source.file_name().unwrap().to_str().unwrap())
-                    })
-                    .collect::<Vec<_>>();
+                    }
+                    Ordering::Equal => {}
+                }
+            }
+        }
+        album_target = Some(album_dir.clone());
+        report::record(|report| report.target = Some(album_dir.clone()));
+
+        let _permit = sync.disk.acquire().await.unwrap();
+        let source = path.clone();
+        let checksum = sync.checksum;
+        let backup_dir = sync
+            .backup_dir
+            .as_ref()
+            .map(|backup_dir| backup_dir.join(&target_artist).join(&target_album));
+        let journal = journal::Journal::new(Path::new(&target_dir), sync.link_mode);
+        let exclude = sync.exclude.clone();
+        let split_dir = (sync.split_cue
+            && cue_sheet
+                .as_ref()
+                .is_some_and(|sheet| !sheet.images().is_empty()))
+        .then(|| {
+            Path::new(&target_dir)
+                .join(".moosicbox_organizer")
+                .join("split")
+                .join(&target_artist)
+                .join(&target_album)
+        });
+        let update = report::spawn_blocking(move || {
+            let Some(split_dir) = split_dir else {
+                return sync_album_files(
+                    &journal,
+                    &source,
+                    &album_dir,
+                    checksum,
+                    backup_dir.as_deref(),
+                    &exclude,
+                );
+            };
+
+            // The split tracks are removed below, so links to them won't do.
+            let mut journal = journal;
+            journal.link_mode = journal::LinkMode::Copy;
+
+            let (cue_path, sheet) = cue::find_cue_sheet(&source).unwrap();
+
+            let update =
+                match sheet.prepare_split(&source, &cue_path, &album_dir, &split_dir, &exclude) {
+                    Ok(split) => {
+                        if split > 0 {
+                            outln!("Split {} tracks out of the CUE image", split);
+                        }
+                        sync_album_files(
+                            &journal,
+                            &split_dir,
+                            &album_dir,
+                            checksum,
+                            backup_dir.as_deref(),
+                            &exclude,
+                        )
+                    }
+                    Err(err) => {
+                        errln!("Failed to split {}: {}", cue_path.to_str().unwrap(), err);
+                        Some(AlbumUpdate {
+                            album_dir,
+                            new_album: false,
+                            added: vec![],
+                            changed: vec![],
+                            failed: sheet.images().into_iter().map(str::to_string).collect(),
+                        })
+                    }
+                };
+
+            let _ = fs::remove_dir_all(&split_dir);
+            update
+        })
+        .await;
 
-                Some(format!(
-                    "{}\n{}",
+        if let Some(update) = &update {
+            if update.failed.is_empty() {
+                outln!(
+                    "Copied album dir {} -> {} ({} new, {} changed)",
+                    path.to_str().unwrap(),
+                    update.album_dir.to_str().unwrap(),
+                    update.added.len(),
+                    update.changed.len()
+                );
+            } else {
+                errln!(
+                    "Incomplete copy of album dir {} -> {} ({} new, {} changed, {} failed)",
+                    path.to_str().unwrap(),
+                    update.album_dir.to_str().unwrap(),
+                    update.added.len(),
+                    update.changed.len(),
+                    update.failed.len()
+                );
+            }
+        }
+
+        report::record(|report| {
+            if let Some(update) = &update {
+                report.files_copied = update.added.clone();
+                report.files_changed = update.changed.clone();
+            } else if !report.errors.is_empty() {
+                return;
+            }
+            report.files_skipped = source_files
+                .iter()
+                .map(|file| file.path.clone())
+                .filter(|file| {
+                    !report.files_copied.contains(file)
+                        && !report.files_changed.contains(file)
+                        && !update
+                            .as_ref()
+                            .is_some_and(|update| update.failed.contains(file))
+                })
+                .collect();
+        });
+
+        // Only once the new copy is complete, or it would be lost along with
+        // the old one.
+        if update
+            .as_ref()
+            .is_some_and(|update| update.failed.is_empty())
+        {
+            let backup_dir = sync
+                .backup_dir
+                .as_ref()
+                .map(|backup_dir| backup_dir.join(&target_artist).join(&target_album));
+
+            for file in superseded {
+                if let Some(backup_dir) = &backup_dir {
+                    backup_file(&file, backup_dir);
+                }
+
+                match fs::remove_file(&file) {
+                    Ok(()) => outln!("Removed lower-quality {}", file.to_str().unwrap()),
+                    Err(err) => errln!("Failed to remove {}: {:?}", file.to_str().unwrap(), err),
+                }
+
+                if let Some(library) = library {
+                    if let Err(err) = library.remove_target_file(&file) {
+                        errln!("Failed to update library index: {:?}", err);
+                    }
+                }
+            }
+
+            for dir in replace {
+                let backup_dir = sync
+                    .backup_dir
+                    .as_ref()
+                    .map(|backup_dir| backup_dir.join(&target_artist));
+
+                match dupes::remove_album_dir(&dir, backup_dir.as_deref()) {
+                    Ok(()) => outln!("Removed lower-quality copy {}", dir.to_str().unwrap()),
+                    Err(err) => errln!("Failed to remove {}: {:?}", dir.to_str().unwrap(), err),
+                }
+
+                if let Some(library) = library {
+                    forget_target_album(library, &dir);
+                }
+            }
+        }
+
+        update
+    } else {
+        None
+    };
+
+    // Whether every step went through. Otherwise the album is recorded
+    // without its files, so the next run doesn't take it as unchanged.
+    let mut complete = updated
+        .as_ref()
+        .is_none_or(|update| update.failed.is_empty());
+
+    if let Some(transcode) = &sync.transcode {
+        let album_dir = transcode
+            .target_dir
+            .join(&target_artist)
+            .join(&target_album);
+
+        match transcode::transcode_album(transcode, &path, &album_dir, &sync.exclude).await {
+            Some(transcoded) => {
+                outln!(
+                    "Transcoded album dir {} -> {} ({} files)",
+                    path.to_str().unwrap(),
                     album_dir.to_str().unwrap(),
-                    copied_files.join("\n"),
-                ))
+                    transcoded.written.len()
+                );
+                complete &= transcoded.failed.is_empty();
             }
+            None => complete = false,
+        }
+    }
+
+    if let Some(library) = library {
+        let source = path.clone();
+        let hash_files = library.hash_files;
+        // Re-listed so covers downloaded during this run are recorded too.
+        let files = report::spawn_blocking(move || {
+            let mut files = index::list_source_files(&source);
+            index::fill_hashes(&source, &mut files, &indexed_files, hash_files);
+            files
+        })
+        .await;
+
+        let cover_status = if contains_file_with_prefix(&path, "cover.") {
+            index::CoverStatus::Present
         } else {
-            None
+            index::CoverStatus::Missing
+        };
+
+        let record = index::AlbumRecord {
+            source_path: &path,
+            artist,
+            album,
+            year,
+            musicbrainz_release_id: raw_tags
+                .get("MUSICBRAINZ_ALBUMID")
+                .and_then(|id| musicbrainz::parse_mbid(id)),
+            target_path: album_target.as_deref(),
+            cover_status,
+            files: if complete { &files } else { &[] },
+        };
+
+        if let Err(err) = library.record_album(&record) {
+            errln!("Failed to update library index: {:?}", err);
         }
+
+        if let Some(update) = &updated {
+            let (source, album_dir) = (path.clone(), update.album_dir.clone());
+            // Links change along with the source, so there's no copy of
+            // their own to keep a manifest entry for.
+            let (linked, written) = update
+                .added
+                .iter()
+                .chain(update.changed.iter())
+                .cloned()
+                .partition::<Vec<_>, _>(|file| {
+                    journal::is_linked(&source.join(file), &album_dir.join(file))
+                });
+            let manifest = report::spawn_blocking(move || {
+                written
+                    .into_iter()
+                    .filter_map(|file| {
+                        let target = album_dir.join(&file);
+                        let size = fs::metadata(&target).ok()?.len();
+                        let hash = index::hash_file(&target)
+                            .map_err(|err| errln!("Failed to hash {}: {:?}", file, err))
+                            .ok()?;
+                        Some(index::ManifestEntry {
+                            path: file,
+                            size,
+                            hash,
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .await;
+
+            if let Err(err) = library.update_manifest(&path, &manifest) {
+                errln!("Failed to update library index: {:?}", err);
+            }
+
+            for file in linked {
+                if let Err(err) = library.remove_target_file(&update.album_dir.join(file)) {
+                    errln!("Failed to update library index: {:?}", err);
+                }
+            }
+        }
+    }
+
+    updated
+}
+
+/// What's read from the files of an album before processing it.
+struct AlbumFiles {
+    audio_files: Vec<(PathBuf, formats::AudioFormat)>,
+    /// The tags of the first audio file, which stand for the album's.
+    raw_tags: raw_tags::RawTags,
+    tags: formats::TrackTags,
+    cue_sheet: Option<(PathBuf, cue::CueSheet)>,
+    quality: quality::AlbumQuality,
+    positions: Vec<completeness::TrackPosition>,
+}
+
+/// Reads the audio files of the album at `dir`, or returns `None` if it has
+/// none.
+fn inspect_album(dir: &Path) -> Option<AlbumFiles> {
+    let audio_files = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|p| p.ok())
+        .map(|p| p.path())
+        .filter_map(|path| formats::detect(&path).map(|format| (path, format)))
+        .collect::<Vec<_>>();
+
+    let (music_file, format) = audio_files.first()?;
+    let raw_tags = raw_tags::read_raw_tags(music_file, *format);
+    let tags = formats::read_track_tags(music_file, *format, &raw_tags);
+
+    Some(AlbumFiles {
+        raw_tags,
+        tags,
+        cue_sheet: cue::find_cue_sheet(dir),
+        quality: quality::AlbumQuality {
+            tracks: audio_files
+                .iter()
+                .map(|(file, format)| quality::inspect(file, *format))
+                .collect(),
+        },
+        positions: audio_files
+            .iter()
+            .map(|(file, format)| completeness::read_position(file, *format))
+            .collect(),
+        audio_files,
+    })
+}
+
+/// How albums are written to the target, shared by every album in a run.
+struct SyncOptions {
+    disk: Semaphore,
+    /// Compare file contents instead of modification times.
+    checksum: bool,
+    /// Where overwritten target files are copied to, mirroring the target layout.
+    backup_dir: Option<PathBuf>,
+    /// Cut single-file CUE images into one file per track.
+    split_cue: bool,
+    transcode: Option<transcode::TranscodeOptions>,
+    /// Checks new albums against the ones already under their artist.
+    dupes: Option<dupes::DupeOptions>,
+    quality_policy: quality::QualityPolicy,
+    /// Where albums go that lose to the target copy under `quality_policy`,
+    /// instead of being skipped.
+    lower_quality_dir: Option<PathBuf>,
+    /// Ask MusicBrainz how many tracks an album should have when its tags
+    /// don't say.
+    musicbrainz_track_counts: bool,
+    link_mode: journal::LinkMode,
+    exclude: Arc<exclude::Exclude>,
+    naming: naming::Naming,
+}
+
+/// Forgets the albums the index has placed at `album_dir`, which is gone.
+fn forget_target_album(library: &index::LibraryIndex, album_dir: &Path) {
+    for album in library
+        .albums()
+        .into_iter()
+        .filter(|album| album.target_path.as_deref() == Some(album_dir))
+    {
+        if let Err(err) = library.remove_album(&album.source_path) {
+            errln!("Failed to update library index: {:?}", err);
+        }
+    }
+}
+
+/// What was copied into an album directory in the target.
+struct AlbumUpdate {
+    album_dir: PathBuf,
+    new_album: bool,
+    added: Vec<String>,
+    changed: Vec<String>,
+    /// Files that couldn't be written, leaving the target copy incomplete.
+    failed: Vec<String>,
+}
+
+fn print_album_update(update: &AlbumUpdate) {
+    if update.new_album {
+        outln!("{} (new album)", update.album_dir.to_str().unwrap());
+        return;
+    }
+    outln!("{}", update.album_dir.to_str().unwrap());
+    update
+        .added
+        .iter()
+        .for_each(|file| outln!("\tadded: {file}"));
+    update
+        .changed
+        .iter()
+        .for_each(|file| outln!("\tchanged: {file}"));
+    update
+        .failed
+        .iter()
+        .for_each(|file| outln!("\tfailed: {file}"));
+}
+
+/// The album directories at `path`: its subdirectories if it has any (an
+/// artist directory), otherwise `path` itself.
+fn album_dirs_under(path: PathBuf) -> Vec<PathBuf> {
+    let children = path
+        .read_dir()
+        .map(|entries| entries.filter_map(|f| f.ok()).map(|f| f.path()).collect())
+        .unwrap_or_else(|_| vec![]);
+
+    if children.iter().any(|p: &PathBuf| p.is_dir()) {
+        children.into_iter().filter(|p| p.is_dir()).collect()
     } else {
-        None
+        vec![path]
+    }
+}
+
+/// Whether the `target` copy of `source` is out of date: the sizes differ, or
+/// `source` was modified after `target` was written (or, with `checksum`, the
+/// contents differ).
+fn file_changed(source: &Path, target: &Path, checksum: bool) -> bool {
+    let (Ok(source_metadata), Ok(target_metadata)) = (fs::metadata(source), fs::metadata(target))
+    else {
+        return true;
+    };
+
+    if source_metadata.len() != target_metadata.len() {
+        return true;
+    }
+
+    if checksum {
+        return match (index::hash_file(source), index::hash_file(target)) {
+            (Ok(source_hash), Ok(target_hash)) => source_hash != target_hash,
+            _ => true,
+        };
+    }
+
+    match (source_metadata.modified(), target_metadata.modified()) {
+        (Ok(source_modified), Ok(target_modified)) => source_modified > target_modified,
+        _ => false,
+    }
+}
+
+/// Copies `target` into `backup_dir` before it gets overwritten, replacing
+/// an earlier backup of the same name.
+fn backup_file(target: &Path, backup_dir: &Path) {
+    if let Err(err) = fs::create_dir_all(backup_dir) {
+        errln!("Failed to create backup dir: {:?}", err);
+        return;
+    }
+
+    let backup = backup_dir.join(target.file_name().unwrap());
+
+    if let Err(err) = fs::copy(target, &backup) {
+        errln!(
+            "Failed to back up {} to {}: {:?}",
+            target.to_str().unwrap(),
+            backup.to_str().unwrap(),
+            err
+        );
     }
 }
 
+/// Copies the album at `source` to `album_dir`, or, if it already exists, the
+/// files missing from it and the ones that changed since they were copied.
+/// Returns `None` when the album was already up to date.
+fn sync_album_files(
+    journal: &journal::Journal,
+    source: &Path,
+    album_dir: &Path,
+    checksum: bool,
+    backup_dir: Option<&Path>,
+    exclude: &exclude::Exclude,
+) -> Option<AlbumUpdate> {
+    if !album_dir.is_dir() {
+        let files = index::list_source_files(source)
+            .into_iter()
+            .map(|file| file.path)
+            .filter(|file| !exclude.matches(&source.join(file)))
+            .collect();
+
+        if let Err(err) = journal.copy_album(source, album_dir, exclude) {
+            errln!(
+                "Failed to copy {} -> {}: {:?}",
+                source.to_str().unwrap(),
+                album_dir.to_str().unwrap(),
+                err
+            );
+            return Some(AlbumUpdate {
+                album_dir: album_dir.to_path_buf(),
+                new_album: false,
+                added: vec![],
+                changed: vec![],
+                failed: files,
+            });
+        }
+        return Some(AlbumUpdate {
+            album_dir: album_dir.to_path_buf(),
+            new_album: true,
+            added: files,
+            changed: vec![],
+            failed: vec![],
+        });
+    }
+
+    // The files to write, and whether each replaces an outdated copy.
+    let pending = fs::read_dir(source)
+        .unwrap()
+        .filter_map(|p| p.ok())
+        .filter(|p| p.path().is_file())
+        .map(|p| p.path())
+        .filter(|path| !exclude.matches(path))
+        .filter_map(|source_file| {
+            let file_name = source_file.file_name()?.to_str()?.to_string();
+            let target = album_dir.join(&file_name);
+
+            if !target.is_file() {
+                Some((source_file, file_name, false))
+            } else if file_changed(&source_file, &target, checksum) {
+                Some((source_file, file_name, true))
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if pending.is_empty() {
+        return None;
+    }
+
+    let mut update = AlbumUpdate {
+        album_dir: album_dir.to_path_buf(),
+        new_album: false,
+        added: vec![],
+        changed: vec![],
+        failed: vec![],
+    };
+
+    let journal_entry = match journal.begin_update(source, album_dir) {
+        Ok(entry) => entry,
+        Err(err) => {
+            errln!("Failed to write journal entry: {:?}", err);
+            update.failed = pending.into_iter().map(|(_, name, _)| name).collect();
+            update.failed.sort();
+            return Some(update);
+        }
+    };
+
+    for (source_file, file_name, replaces) in pending {
+        let target = album_dir.join(&file_name);
+
+        if replaces {
+            if let Some(backup_dir) = backup_dir {
+                backup_file(&target, backup_dir);
+            }
+        }
+
+        match journal::replace_file(&source_file, &target, journal.link_mode) {
+            Ok(()) if replaces => update.changed.push(file_name),
+            Ok(()) => update.added.push(file_name),
+            Err(err) => {
+                errln!(
+                    "Failed to copy {} -> {}: {:?}",
+                    source_file.to_str().unwrap(),
+                    target.to_str().unwrap(),
+                    err
+                );
+                update.failed.push(file_name);
+            }
+        }
+    }
+
+    journal.end_update(&journal_entry, album_dir);
+
+    update.added.sort();
+    update.changed.sort();
+    update.failed.sort();
+
+    Some(update)
+}
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    #[arg(short, long)]
-    source: String,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    #[arg(short, long)]
+    /// Config file [default: $XDG_CONFIG_HOME/moosicbox_organizer/config.toml, if it exists]
+    #[arg(long, env = "MOOSICBOX_ORGANIZER_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Source/target pair from the config file to use, instead of the first one
+    #[arg(long, env = "MOOSICBOX_ORGANIZER_PAIR")]
+    pair: Option<String>,
+
+    /// Required, unless the config file has a source/target pair
+    #[arg(short, long, env = "MOOSICBOX_ORGANIZER_SOURCE")]
+    source: Option<String>,
+
+    #[arg(short, long, env = "MOOSICBOX_ORGANIZER_TARGET")]
     target: Option<String>,
 
-    #[arg(short, long)]
+    #[arg(short, long, env = "MOOSICBOX_ORGANIZER_COVERS")]
     covers: bool,
 
-    #[arg(long)]
+    #[arg(long, value_enum, default_value_t = CoverSize::Large, env = "MOOSICBOX_ORGANIZER_COVER_SIZE")]
+    cover_size: CoverSize,
+
+    /// Album cover providers to fall back to, in order, when Tidal has no art
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "musicbrainz,deezer,itunes,discogs",
+        env = "MOOSICBOX_ORGANIZER_PROVIDERS"
+    )]
+    providers: Vec<Provider>,
+
+    #[arg(long, env = "MOOSICBOX_ORGANIZER_CREDS")]
     creds: Option<String>,
+
+    /// How files are put into the target
+    #[arg(long, value_enum, default_value_t = journal::LinkMode::Copy, env = "MOOSICBOX_ORGANIZER_LINK_MODE")]
+    link_mode: journal::LinkMode,
+
+    /// Leave out source directories and files matching this glob, relative to --source (repeatable)
+    #[arg(long, env = "MOOSICBOX_ORGANIZER_EXCLUDE")]
+    exclude: Vec<String>,
+
+    /// Name of artist directories in the target; fields: {artist}, {album}, {year}, {album_dir}
+    #[arg(long, default_value = naming::DEFAULT_ARTIST_DIR, env = "MOOSICBOX_ORGANIZER_ARTIST_DIR_TEMPLATE")]
+    artist_dir_template: String,
+
+    /// Name of album directories in the target; fields: {artist}, {album}, {year}, {album_dir}
+    #[arg(long, default_value = naming::DEFAULT_ALBUM_DIR, env = "MOOSICBOX_ORGANIZER_ALBUM_DIR_TEMPLATE")]
+    album_dir_template: String,
+
+    #[arg(long, default_value = discogs::DEFAULT_API_URL)]
+    discogs_api_url: String,
+
+    #[arg(long, default_value = fanart::DEFAULT_API_URL)]
+    fanart_api_url: String,
+
+    /// Kinds of fanart.tv artist artwork to download into the artist directory
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "thumb,backdrop,logo,banner"
+    )]
+    fanart_kinds: Vec<fanart::ArtworkKind>,
+
+    /// Where provider responses are cached [default: $XDG_CACHE_HOME/moosicbox_organizer]
+    #[arg(long)]
+    cache_dir: Option<PathBuf>,
+
+    /// Don't read or write the provider response cache
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Ignore cached provider responses, re-fetching (and re-caching) them
+    #[arg(long)]
+    refresh_cache: bool,
+
+    #[arg(long, default_value_t = 30)]
+    cache_ttl_days: u64,
+
+    /// How long "no match" results are cached before being looked up again
+    #[arg(long, default_value_t = 7)]
+    negative_cache_ttl_days: u64,
+
+    #[arg(long, default_value_t = 60)]
+    http_timeout_secs: u64,
+
+    /// Retries of a failed provider request (timeouts, 429s and 5xx responses)
+    #[arg(long, default_value_t = 3)]
+    retries: u32,
+
+    #[arg(long, default_value_t = 500)]
+    retry_base_delay_ms: u64,
+
+    /// Longest wait between retries, including waits requested via Retry-After
+    #[arg(long, default_value_t = 30)]
+    retry_max_delay_secs: u64,
+
+    /// Consecutive failed requests after which a provider is disabled for the rest of the run
+    #[arg(long, default_value_t = 5)]
+    circuit_breaker_threshold: u32,
+
+    /// Number of albums processed concurrently
+    #[arg(short, long, default_value_t = 1)]
+    jobs: usize,
+
+    /// Number of albums copied to the target concurrently
+    #[arg(long, default_value_t = 2)]
+    disk_jobs: usize,
+
+    /// Detect changed files by comparing their contents rather than size and modification time
+    #[arg(long)]
+    checksum: bool,
+
+    /// Copy target files into this directory before overwriting them, replacing any earlier backup of the same file
+    #[arg(long)]
+    backup_dir: Option<PathBuf>,
+
+    /// Cut albums ripped as a single image plus CUE sheet into per-track files (needs ffmpeg)
+    #[arg(long)]
+    split_cue: bool,
+
+    /// Check albums new to the target for duplicates under the same artist, and what to do with them
+    #[arg(long, value_enum)]
+    on_duplicate: Option<dupes::DupePolicy>,
+
+    /// Also compare audio fingerprints (with Chromaprint's fpcalc) when looking for duplicates
+    #[arg(long)]
+    fingerprint: bool,
+
+    /// What to do when the target already has an album in a different quality
+    #[arg(long, value_enum, default_value_t = quality::QualityPolicy::Merge)]
+    quality_policy: quality::QualityPolicy,
+
+    /// Copy albums of lower quality than the target copy here instead of skipping them
+    #[arg(long)]
+    lower_quality_dir: Option<PathBuf>,
+
+    /// Look up expected track counts on MusicBrainz for albums whose tags have no track totals
+    #[arg(long)]
+    musicbrainz_track_counts: bool,
+
+    /// Also keep a transcoded copy of the library here, laid out like the target
+    #[arg(long)]
+    transcode_target: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value_t = transcode::Codec::Opus)]
+    transcode_codec: transcode::Codec,
+
+    /// Bitrate of transcoded files, in kbps
+    #[arg(long, default_value_t = 128)]
+    transcode_bitrate: u32,
+
+    #[arg(long, value_enum, default_value_t = transcode::Encoder::Ffmpeg)]
+    transcode_encoder: transcode::Encoder,
+
+    /// Longest side of the cover in the transcoded copy, in pixels
+    #[arg(long, default_value_t = 600)]
+    transcode_cover_size: u32,
+
+    /// Maximum number of encoder processes running at once
+    #[arg(long, default_value_t = std::thread::available_parallelism().map_or(1, |n| n.get()))]
+    transcode_jobs: usize,
+
+    /// Concurrent requests per artwork provider (MusicBrainz is always limited to 1)
+    #[arg(long, default_value_t = 2)]
+    provider_jobs: usize,
+
+    /// Library index database [default: $XDG_DATA_HOME/moosicbox_organizer/library.db]
+    #[arg(long)]
+    index: Option<PathBuf>,
+
+    /// Don't read or write the library index, processing every album
+    #[arg(long)]
+    no_index: bool,
+
+    /// Process every album, even those the index says are unchanged
+    #[arg(long)]
+    rescan: bool,
+
+    /// Record SHA-256 hashes of new and changed source files in the index
+    #[arg(long)]
+    hash_files: bool,
+
+    /// Delete target files of indexed albums that no longer exist in the source
+    #[arg(long, conflicts_with = "no_index")]
+    mirror: bool,
+
+    /// Don't ask before deleting files in mirror mode
+    #[arg(short, long)]
+    yes: bool,
+
+    /// Don't ask before deleting the target copies of albums removed from the
+    /// source as a whole in mirror mode, which --yes doesn't cover
+    #[arg(long, requires = "mirror")]
+    delete_removed_albums: bool,
+
+    /// Log more: debug output, including the tags read from each album
+    #[arg(short, long, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Log less: -q for warnings and errors only, -qq for errors only
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    quiet: u8,
+
+    /// Per-module log levels, e.g. "warn,http=debug,discogs=off"
+    #[arg(long)]
+    log_filter: Option<output::Filter>,
+
+    #[arg(long, value_enum, default_value_t = output::LogFormat::Text)]
+    log_format: output::LogFormat,
+
+    /// Don't show the progress display, even when stdout is a terminal
+    #[arg(long)]
+    no_progress: bool,
+
+    /// Write a JSON report of the run (what was copied, covers, errors and timings) to this file
+    #[arg(long)]
+    report: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Manage the provider response cache
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    /// Query the library index
+    Index {
+        #[command(subcommand)]
+        command: IndexCommand,
+    },
+    /// Watch --source and organize albums as they are added or changed
+    Watch {
+        /// Seconds an album directory has to go without changes before it's processed
+        #[arg(long, default_value_t = 30)]
+        quiet_secs: u64,
+    },
+    /// Report missing, duplicated and unnumbered tracks of the albums in --target, or --source without one
+    Check,
+    /// Find albums in --target that duplicate each other
+    Dupes {
+        /// Delete every copy but the highest-quality one
+        #[arg(long)]
+        remove_lower_quality: bool,
+    },
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Check the target copies of indexed albums against the manifest
+    Verify {
+        /// Replace damaged or missing files with intact copies from the source
+        #[arg(long)]
+        restore: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum IndexCommand {
+    /// List indexed albums
+    List {
+        /// Only albums without a cover
+        #[arg(long)]
+        missing_cover: bool,
+
+        /// Only albums by artists containing this text
+        #[arg(long)]
+        artist: Option<String>,
+    },
+    /// Show totals for the indexed library
+    Stats,
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the effective configuration: the config file merged with the environment and flags
+    Show,
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheCommand {
+    /// Remove expired entries
+    Prune {
+        /// Remove every entry, not just expired ones
+        #[arg(long)]
+        all: bool,
+    },
+}
+
+/// The usage error for `command`, which needs the library index, when it
+/// isn't available.
+fn index_unavailable(no_index: bool, command: &str) -> clap::Error {
+    if no_index {
+        Args::command().error(
+            ErrorKind::ArgumentConflict,
+            format!("{command} can't be used with --no-index"),
+        )
+    } else {
+        Args::command().error(
+            ErrorKind::MissingRequiredArgument,
+            "no default library index location on this system, pass --index",
+        )
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -433,14 +1463,252 @@ struct Creds {
     tidal_refresh_token: Option<String>,
 
     tidal_access_token: Option<String>,
+
+    discogs_token: Option<String>,
+
+    fanart_api_key: Option<String>,
 }
 
 #[tokio::main]
 async fn main() {
-    let args = Args::parse();
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+
+    let config_path = args
+        .config
+        .clone()
+        .or_else(|| config::default_path().filter(|path| path.is_file()));
+    if let Some(path) = &config_path {
+        if let Err(err) = config::load(path).and_then(|config| config.apply(&mut args, &matches)) {
+            Args::command()
+                .error(
+                    ErrorKind::InvalidValue,
+                    format!("invalid config file {}: {err}", path.display()),
+                )
+                .exit();
+        }
+    }
+    if let Err(err) = config::validate(&args) {
+        Args::command().error(ErrorKind::InvalidValue, err).exit();
+    }
+
+    if let Some(Command::Config {
+        command: ConfigCommand::Show,
+    }) = &args.command
+    {
+        match &config_path {
+            Some(path) => println!("# Config file: {}", path.display()),
+            None => println!("# No config file"),
+        }
+        print!("{}", config::Config::effective(&args).to_toml());
+        return;
+    }
+
+    output::init(output::LogConfig {
+        level: output::Level::from_verbosity(args.verbose, args.quiet),
+        filter: args.log_filter.clone().unwrap_or_default(),
+        format: args.log_format,
+    });
 
     let start = SystemTime::now();
 
+    let cache = if args.no_cache {
+        None
+    } else {
+        args.cache_dir
+            .clone()
+            .or_else(cache::default_dir)
+            .map(|dir| {
+                cache::Cache::new(
+                    dir,
+                    Duration::from_secs(args.cache_ttl_days * 24 * 60 * 60),
+                    Duration::from_secs(args.negative_cache_ttl_days * 24 * 60 * 60),
+                    args.refresh_cache,
+                )
+            })
+    };
+
+    if let Some(Command::Cache {
+        command: CacheCommand::Prune { all },
+    }) = args.command
+    {
+        let Some(cache) = cache else {
+            let error = if args.no_cache {
+                Args::command().error(
+                    ErrorKind::ArgumentConflict,
+                    "cache prune can't be used with --no-cache",
+                )
+            } else {
+                Args::command().error(
+                    ErrorKind::MissingRequiredArgument,
+                    "no default cache dir on this system, pass --cache-dir",
+                )
+            };
+            error.exit();
+        };
+        let (removed, kept) = cache.prune(all);
+        println!("Removed {removed} cache entries, {kept} remaining");
+        return;
+    }
+
+    let library = if args.no_index {
+        None
+    } else {
+        args.index.clone().or_else(index::default_path).map(|path| {
+            index::LibraryIndex::open(&path, args.hash_files, args.rescan)
+                .expect("Failed to open library index")
+        })
+    };
+
+    if let Some(Command::Index { command }) = args.command {
+        let Some(library) = library else {
+            index_unavailable(args.no_index, "index").exit();
+        };
+        match command {
+            IndexCommand::List {
+                missing_cover,
+                artist,
+            } => {
+                let artist = artist.map(|artist| artist.to_lowercase());
+                library
+                    .albums()
+                    .iter()
+                    .filter(|album| {
+                        !missing_cover || album.cover_status == index::CoverStatus::Missing
+                    })
+                    .filter(|album| {
+                        artist
+                            .as_ref()
+                            .is_none_or(|artist| album.artist.to_lowercase().contains(artist))
+                    })
+                    .for_each(|album| {
+                        println!(
+                            "{} - {}{}\t{}\t{}\tcover: {}\trelease: {}",
+                            album.artist,
+                            album.album,
+                            album
+                                .year
+                                .map(|year| format!(" ({year})"))
+                                .unwrap_or_default(),
+                            album.source_path.to_str().unwrap(),
+                            album
+                                .target_path
+                                .as_ref()
+                                .map_or("-", |path| path.to_str().unwrap()),
+                            album.cover_status.as_str(),
+                            album.musicbrainz_release_id.as_deref().unwrap_or("-"),
+                        )
+                    });
+            }
+            IndexCommand::Stats => {
+                let albums = library.albums();
+                let artists = albums
+                    .iter()
+                    .map(|album| album.artist.as_str())
+                    .collect::<std::collections::HashSet<_>>();
+                println!("Albums: {}", albums.len());
+                println!("Artists: {}", artists.len());
+                println!(
+                    "Files: {}",
+                    albums.iter().map(|album| album.file_count).sum::<usize>()
+                );
+                println!(
+                    "Size: {} bytes",
+                    albums.iter().map(|album| album.total_size).sum::<u64>()
+                );
+                println!(
+                    "Missing covers: {}",
+                    albums
+                        .iter()
+                        .filter(|album| album.cover_status == index::CoverStatus::Missing)
+                        .count()
+                );
+            }
+        }
+        return;
+    }
+
+    if let Some(Command::Verify { restore }) = args.command {
+        let Some(library) = library else {
+            index_unavailable(args.no_index, "verify").exit();
+        };
+        let summary = verify::verify(&library, restore);
+        println!("==================================================");
+        println!(
+            "Checked {} files: {} missing, {} mismatched, {} unreadable, {} restored",
+            summary.checked,
+            summary.missing,
+            summary.mismatched,
+            summary.unreadable,
+            summary.restored
+        );
+        if summary.recorded > 0 {
+            println!("Added {} files to the manifest", summary.recorded);
+        }
+        if summary.linked > 0 {
+            println!("Skipped {} files linked to the source", summary.linked);
+        }
+        if summary.unresolved() > 0 {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(Command::Dupes {
+        remove_lower_quality,
+    }) = args.command
+    {
+        let Some(target_dir) = args.target.clone() else {
+            Args::command()
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    "dupes requires --target",
+                )
+                .exit();
+        };
+        let groups = dupes::find_in_target(Path::new(&target_dir), args.fingerprint);
+
+        for group in &groups {
+            println!("Duplicates:");
+            group.iter().for_each(|album| {
+                println!("\t{} ({})", album.dir.to_str().unwrap(), album.quality)
+            });
+        }
+        println!("Found {} duplicated albums", groups.len());
+
+        let lower_quality = groups
+            .iter()
+            .flat_map(|group| group.iter().skip(1))
+            .map(|album| album.dir.clone())
+            .collect::<Vec<_>>();
+
+        if remove_lower_quality
+            && !lower_quality.is_empty()
+            && mirror::ask(
+                &format!("Delete {} lower-quality copies?", lower_quality.len()),
+                args.yes,
+                "--yes",
+            )
+        {
+            for dir in lower_quality {
+                let backup_dir = args
+                    .backup_dir
+                    .as_ref()
+                    .and_then(|backup_dir| Some(backup_dir.join(dir.parent()?.file_name()?)));
+
+                match dupes::remove_album_dir(&dir, backup_dir.as_deref()) {
+                    Ok(()) => println!("Removed {}", dir.to_str().unwrap()),
+                    Err(err) => errln!("Failed to remove {}: {:?}", dir.to_str().unwrap(), err),
+                }
+
+                if let Some(library) = &library {
+                    forget_target_album(library, &dir);
+                }
+            }
+        }
+        return;
+    }
+
     let mut default_headers = header::HeaderMap::new();
     default_headers.insert(
         "Accept",
@@ -450,13 +1718,79 @@ async fn main() {
         "User-Agent",
         header::HeaderValue::from_static("PostmanRuntime/7.33.0"),
     );
-    let artwork_client = Client::builder()
+    let client = Client::builder()
         .default_headers(default_headers)
-        .timeout(Duration::from_secs(60))
+        .timeout(Duration::from_secs(args.http_timeout_secs))
         .build()
         .unwrap();
 
-    let source_dir = args.source;
+    let artwork_client = http::HttpClient::new(
+        client.clone(),
+        cache,
+        http::RetryPolicy {
+            max_retries: args.retries,
+            base_delay: Duration::from_millis(args.retry_base_delay_ms),
+            max_delay: Duration::from_secs(args.retry_max_delay_secs),
+            circuit_breaker_threshold: args.circuit_breaker_threshold,
+        },
+        args.provider_jobs.max(1),
+    )
+    .with_rate_limiter("discogs", discogs::RateLimit::default());
+
+    if let Some(Command::Check) = args.command {
+        let Some(dir) = args.target.as_ref().or(args.source.as_ref()) else {
+            Args::command()
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    "check requires --target or --source",
+                )
+                .exit();
+        };
+        let mut checked = 0;
+        let mut incomplete = 0;
+
+        for album_dir in fs::read_dir(dir)
+            .unwrap()
+            .filter_map(|p| p.ok())
+            .filter(|p| p.path().is_dir() && !p.file_name().to_string_lossy().starts_with('.'))
+            .flat_map(|p| album_dirs_under(p.path()))
+        {
+            let positions = completeness::read_positions(&album_dir);
+            if positions.is_empty() {
+                continue;
+            }
+            checked += 1;
+
+            let expected_tracks = if args.musicbrainz_track_counts {
+                completeness::expected_track_counts(&artwork_client, &positions).await
+            } else {
+                None
+            };
+            let issues = completeness::check(&positions, expected_tracks.as_deref());
+
+            if !issues.is_empty() {
+                incomplete += 1;
+                println!("{}", album_dir.to_str().unwrap());
+                issues.iter().for_each(|issue| println!("\t{issue}"));
+            }
+        }
+
+        println!("==================================================");
+        println!("Checked {checked} albums: {incomplete} incomplete");
+        if incomplete > 0 {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let Some(source_dir) = args.source else {
+        Args::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "--source is required, unless the config file has a source/target pair",
+            )
+            .exit();
+    };
     let target_dir = args.target;
     let fetch_covers = args.covers;
 
@@ -468,9 +1802,19 @@ async fn main() {
         None
     };
 
+    let discogs = creds
+        .as_ref()
+        .and_then(|creds| creds.discogs_token.clone())
+        .map(|token| discogs::Discogs::new(&args.discogs_api_url, token));
+
+    let fanart = creds
+        .as_ref()
+        .and_then(|creds| creds.fanart_api_key.clone())
+        .map(|api_key| fanart::FanartTv::new(&args.fanart_api_url, api_key, args.fanart_kinds));
+
     let tidal_access_token = if let Some(creds) = creds {
-        Some(if let Some(access_token) = creds.tidal_access_token {
-            access_token
+        if let Some(access_token) = creds.tidal_access_token {
+            Some(access_token)
         } else if let Some(refresh_token) = creds.tidal_refresh_token {
             let params = [
                 (
@@ -482,7 +1826,7 @@ async fn main() {
                 ("scope", "r_usr w_usr".to_string()),
             ];
 
-            let response = artwork_client
+            let response = client
                 .post(creds.tidal_token_url.expect("Missing tokenUrl"))
                 .form(&params)
                 .send()
@@ -492,66 +1836,266 @@ async fn main() {
                 .await
                 .expect("Failed to get access_token from token url");
 
-            response
-                .get("access_token")
-                .expect("No access_token on response")
-                .as_str()
-                .expect("access_token is not a string")
-                .to_string()
+            Some(
+                response
+                    .get("access_token")
+                    .expect("No access_token on response")
+                    .as_str()
+                    .expect("access_token is not a string")
+                    .to_string(),
+            )
+        } else if creds.discogs_token.is_some() || creds.fanart_api_key.is_some() {
+            None
         } else {
             panic!("Invalid creds file");
-        })
+        }
     } else {
         None
     };
 
-    let mut updated = Vec::new();
+    let artwork = ArtworkOptions {
+        tidal_auth: tidal_access_token,
+        cover_size: args.cover_size,
+        providers: args.providers,
+        discogs,
+        fanart,
+    };
+
+    if let Some(target_dir) = &target_dir {
+        for source in journal::Journal::new(Path::new(target_dir), args.link_mode).recover() {
+            // Forgotten so the index doesn't skip the album as unchanged.
+            if let Some(library) = &library {
+                if let Err(err) = library.remove_album(&source) {
+                    errln!("Failed to update library index: {:?}", err);
+                }
+            }
+        }
+    }
 
-    for path in fs::read_dir(source_dir)
+    let profile = transcode::Profile {
+        codec: args.transcode_codec,
+        bitrate: args.transcode_bitrate,
+        encoder: args.transcode_encoder,
+        cover_size: args.transcode_cover_size,
+    };
+    if args.transcode_target.is_some() {
+        if let Err(err) = profile.check_encoders() {
+            errln!("Cannot transcode: {err}");
+            std::process::exit(1);
+        }
+    }
+
+    let exclude = match exclude::Exclude::new(Path::new(&source_dir), &args.exclude) {
+        Ok(exclude) => Arc::new(exclude),
+        Err(err) => Args::command()
+            .error(
+                ErrorKind::InvalidValue,
+                format!("invalid --exclude glob: {err}"),
+            )
+            .exit(),
+    };
+
+    let sync = SyncOptions {
+        disk: Semaphore::new(args.disk_jobs.max(1)),
+        checksum: args.checksum,
+        backup_dir: args.backup_dir,
+        split_cue: args.split_cue,
+        transcode: args
+            .transcode_target
+            .map(|target_dir| transcode::TranscodeOptions {
+                target_dir,
+                profile,
+                jobs: Semaphore::new(args.transcode_jobs.max(1)),
+            }),
+        dupes: args.on_duplicate.map(|policy| dupes::DupeOptions {
+            policy,
+            fingerprint: args.fingerprint,
+        }),
+        quality_policy: args.quality_policy,
+        lower_quality_dir: args.lower_quality_dir,
+        musicbrainz_track_counts: args.musicbrainz_track_counts,
+        link_mode: args.link_mode,
+        exclude,
+        naming: naming::Naming {
+            artist_dir: args.artist_dir_template,
+            album_dir: args.album_dir_template,
+        },
+    };
+
+    if let Some(Command::Watch { quiet_secs }) = args.command {
+        let (artwork_client, artwork, sync, library) =
+            (&artwork_client, &artwork, &sync, library.as_ref());
+
+        let watched = watch::watch(
+            Path::new(&source_dir),
+            Duration::from_secs(quiet_secs),
+            |dir| {
+                let target_dir = target_dir.clone();
+                async move {
+                    for album_dir in album_dirs_under(dir)
+                        .into_iter()
+                        .filter(|dir| !sync.exclude.matches(dir))
+                    {
+                        let album = copy_album_dir_contents(
+                            target_dir.clone(),
+                            album_dir.clone(),
+                            artwork_client,
+                            fetch_covers,
+                            artwork,
+                            sync,
+                            library,
+                        );
+
+                        match AssertUnwindSafe(album).catch_unwind().await {
+                            Ok(Some(update)) => print_album_update(&update),
+                            Ok(None) => {}
+                            Err(_) => {
+                                errln!("Failed to process {}", album_dir.to_str().unwrap())
+                            }
+                        }
+                    }
+                }
+            },
+        )
+        .await;
+
+        if let Err(err) = watched {
+            errln!("Failed to watch {source_dir}: {:?}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let album_dirs = fs::read_dir(&source_dir)
         .unwrap()
         .filter_map(|p| p.ok())
         .filter(|p| p.metadata().unwrap().is_dir())
-        .map(|p| p.path())
-    {
-        let children = path
-            .read_dir()
-            .unwrap()
-            .filter_map(|f| f.ok())
-            .map(|f| f.path())
-            .collect::<Vec<_>>();
+        .flat_map(|p| album_dirs_under(p.path()))
+        .filter(|dir| !sync.exclude.matches(dir))
+        .collect::<Vec<_>>();
 
-        let album_dirs = if children.clone().into_iter().any(|p| p.is_dir()) {
-            children.into_iter().filter(|p| p.is_dir()).collect()
-        } else if path.is_dir() {
-            vec![path]
-        } else {
-            vec![]
-        };
+    let group_output = args.jobs > 1;
 
-        for dir in album_dirs {
-            if let Some(value) = copy_album_dir_contents(
-                target_dir.clone(),
-                dir,
-                &artwork_client,
-                fetch_covers,
-                tidal_access_token.clone(),
-            )
-            .await
-            {
-                updated.push(value);
+    progress::start(
+        album_dirs.len(),
+        !args.no_progress
+            && std::io::stdout().is_terminal()
+            && args.log_format == output::LogFormat::Text,
+    );
+
+    let (updated, album_reports) = futures::stream::iter(album_dirs)
+        .map(|dir| {
+            let album_progress = progress::album(&dir);
+            let album = report::collect(
+                dir.clone(),
+                copy_album_dir_contents(
+                    target_dir.clone(),
+                    dir,
+                    &artwork_client,
+                    fetch_covers,
+                    &artwork,
+                    &sync,
+                    library.as_ref(),
+                ),
+            );
+            async move {
+                let _progress = album_progress;
+                let (update, mut album_report) = if group_output {
+                    output::grouped(album).await
+                } else {
+                    album.await
+                };
+
+                if album_report.status == report::AlbumStatus::UpToDate {
+                    album_report.status = match &update {
+                        Some(update) if !update.failed.is_empty() => report::AlbumStatus::Failed,
+                        Some(update) if update.new_album => report::AlbumStatus::Copied,
+                        Some(_) => report::AlbumStatus::Updated,
+                        None if !album_report.errors.is_empty() => report::AlbumStatus::Failed,
+                        None => report::AlbumStatus::UpToDate,
+                    };
+                }
+
+                (update, album_report)
             }
-        }
-    }
+        })
+        .buffer_unordered(args.jobs.max(1))
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .unzip::<_, _, Vec<_>, Vec<_>>();
+    let updated = updated.into_iter().flatten().collect::<Vec<_>>();
 
-    println!("==================================================");
+    progress::finish();
+
+    outln!("==================================================");
 
     if !updated.is_empty() {
-        println!("Updated following albums:");
-        updated.iter().for_each(|p| println!("{}", p));
+        outln!("Updated following albums:");
+        updated.iter().for_each(print_album_update);
+        outln!(
+            "{} new files, {} changed files, {} failed",
+            updated
+                .iter()
+                .map(|update| update.added.len())
+                .sum::<usize>(),
+            updated
+                .iter()
+                .map(|update| update.changed.len())
+                .sum::<usize>(),
+            updated
+                .iter()
+                .map(|update| update.failed.len())
+                .sum::<usize>()
+        );
     } else {
-        println!("All up-to-date");
+        outln!("All up-to-date");
+    }
+
+    if args.mirror {
+        let target_dir = target_dir.unwrap();
+        let library = library
+            .as_ref()
+            .expect("--mirror requires the library index");
+        match mirror::plan(
+            library,
+            Path::new(&source_dir),
+            Path::new(&target_dir),
+            args.split_cue,
+        ) {
+            Ok(plan) if plan.is_empty() => outln!("Mirror: nothing to delete"),
+            Ok(plan) => {
+                let delete_files = mirror::confirm(&plan, args.yes);
+                let delete_removed_albums =
+                    mirror::confirm_removed_albums(&plan, args.delete_removed_albums);
+                let (files, dirs) =
+                    mirror::apply(&plan, library, delete_files, delete_removed_albums);
+                outln!("Mirror: deleted {files} files and {dirs} directories");
+            }
+            Err(err) => errln!("Not mirroring: {err}"),
+        }
+    }
+
+    let disabled_providers = artwork_client.disabled_providers();
+    if !disabled_providers.is_empty() {
+        outln!("Disabled providers:");
+        disabled_providers
+            .iter()
+            .for_each(|(provider, failures)| outln!("\t{provider} ({failures} failed requests)"));
     }
+
+    if let Some(report_path) = &args.report {
+        match report::RunReport::new(start, album_reports).write(report_path) {
+            Ok(()) => outln!("Wrote report to {}", report_path.to_str().unwrap()),
+            Err(err) => errln!(
+                "Failed to write report {}: {:?}",
+                report_path.to_str().unwrap(),
+                err
+            ),
+        }
+    }
+
     let end = SystemTime::now();
 
-    println!("Took {}ms", end.duration_since(start).unwrap().as_millis());
+    outln!("Took {}ms", end.duration_since(start).unwrap().as_millis());
 }
@@ -0,0 +1,50 @@
+//! Fuzzy string helpers used when comparing local tags against provider
+//! search results.
+
+/// Lowercases `value` and strips everything but alphanumerics, collapsing
+/// runs of whitespace/punctuation into a single space.
+pub fn normalize(value: &str) -> String {
+    let mut normalized = String::with_capacity(value.len());
+    let mut pending_space = false;
+
+    for c in value.chars().flat_map(|c| c.to_lowercase()) {
+        if c.is_alphanumeric() {
+            if pending_space && !normalized.is_empty() {
+                normalized.push(' ');
+            }
+            pending_space = false;
+            normalized.push(c);
+        } else {
+            pending_space = true;
+        }
+    }
+
+    normalized
+}
+
+/// Normalized Levenshtein similarity between `a` and `b` in the range
+/// `0.0..=1.0`, where `1.0` means the normalized strings are identical.
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let a = normalize(a).chars().collect::<Vec<_>>();
+    let b = normalize(b).chars().collect::<Vec<_>>();
+
+    let longest = a.len().max(b.len());
+
+    if longest == 0 {
+        return 1.0;
+    }
+
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    let mut current = vec![0; b.len() + 1];
+
+    for (i, a_char) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    1.0 - previous[b.len()] as f64 / longest as f64
+}
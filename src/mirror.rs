@@ -0,0 +1,488 @@
+//! `--mirror`: removes files from the target that no longer exist in the
+//! source, for the albums the library index knows the organizer placed there.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{BufRead, IsTerminal, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cue,
+    index::{self, LibraryIndex},
+    read_dir_paths,
+};
+
+/// Artwork and metadata the organizer writes into the target itself.
+const PROTECTED_PREFIXES: [&str; 5] = ["cover.", "artist.", "backdrop.", "logo.", "banner."];
+
+fn is_protected(path: &Path) -> bool {
+    let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    let file_name = file_name.to_lowercase();
+
+    PROTECTED_PREFIXES
+        .iter()
+        .any(|prefix| file_name.starts_with(prefix))
+        || file_name.ends_with(".nfo")
+}
+
+/// An indexed album whose source directory no longer exists.
+pub struct RemovedAlbum {
+    pub source: PathBuf,
+    /// Its directory in the target, unless that's gone already or another
+    /// source album still lands there.
+    pub target: Option<PathBuf>,
+}
+
+/// What a mirror pass would delete.
+#[derive(Default)]
+pub struct MirrorPlan {
+    pub files: Vec<PathBuf>,
+    /// Directories left empty once `files` are gone, deepest first.
+    pub dirs: Vec<PathBuf>,
+    /// Albums removed from the source as a whole. Their target copies are
+    /// only deleted once confirmed separately from `files`.
+    pub removed_albums: Vec<RemovedAlbum>,
+}
+
+impl MirrorPlan {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.dirs.is_empty() && self.removed_albums.is_empty()
+    }
+}
+
+/// Works out which files under `target_dir` have no source counterpart. With
+/// `split_cue`, the tracks cut from CUE images count as counterparts.
+///
+/// Refuses to if `source_dir` is missing or empty, as when the drive holding
+/// it isn't mounted, rather than take every album for removed.
+pub fn plan(
+    library: &LibraryIndex,
+    source_dir: &Path,
+    target_dir: &Path,
+    split_cue: bool,
+) -> Result<MirrorPlan, String> {
+    if read_dir_paths(source_dir).is_empty() {
+        return Err(format!(
+            "source {} is missing or empty",
+            source_dir.to_str().unwrap()
+        ));
+    }
+
+    let mut plan = MirrorPlan::default();
+    let mut deleted = HashSet::new();
+    let mut artist_dirs = HashSet::new();
+
+    // Several source albums can land in the same target directory, so a file
+    // is only stale if none of them has it.
+    let mut album_sources = HashMap::<PathBuf, HashSet<String>>::new();
+    let mut removed = vec![];
+
+    for album in library.albums() {
+        let Some(album_dir) = album.target_path else {
+            continue;
+        };
+
+        if !album_dir.starts_with(target_dir) {
+            continue;
+        }
+
+        if !album.source_path.is_dir() {
+            removed.push((album.source_path, album_dir));
+            continue;
+        }
+
+        if !album_dir.is_dir() {
+            continue;
+        }
+
+        let source_files = album_sources.entry(album_dir).or_default();
+        source_files.extend(
+            index::list_source_files(&album.source_path)
+                .into_iter()
+                .map(|file| file.path),
+        );
+
+        if split_cue {
+            if let Some((_, sheet)) = cue::find_cue_sheet(&album.source_path) {
+                source_files.extend(sheet.split_file_names());
+            }
+        }
+    }
+
+    for (source, album_dir) in removed {
+        let target =
+            (album_dir.is_dir() && !album_sources.contains_key(&album_dir)).then_some(album_dir);
+        plan.removed_albums.push(RemovedAlbum { source, target });
+    }
+
+    for (album_dir, source_files) in album_sources {
+        let mut remaining = 0;
+
+        for path in read_dir_paths(&album_dir) {
+            let in_source = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| source_files.contains(name));
+
+            if path.is_file() && !in_source && !is_protected(&path) {
+                deleted.insert(path.clone());
+                plan.files.push(path);
+            } else {
+                remaining += 1;
+            }
+        }
+
+        if remaining == 0 {
+            deleted.insert(album_dir.clone());
+            plan.dirs.push(album_dir.clone());
+        }
+
+        if let Some(artist_dir) = album_dir.parent() {
+            if artist_dir != target_dir {
+                artist_dirs.insert(artist_dir.to_path_buf());
+            }
+        }
+    }
+
+    for artist_dir in artist_dirs {
+        if read_dir_paths(&artist_dir)
+            .iter()
+            .all(|path| deleted.contains(path))
+        {
+            plan.dirs.push(artist_dir);
+        }
+    }
+
+    plan.files.sort();
+    plan.dirs
+        .sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+    plan.removed_albums.sort_by(|a, b| a.source.cmp(&b.source));
+
+    Ok(plan)
+}
+
+/// Prints the files and directories `plan` would delete and asks for
+/// confirmation unless `yes` is set. Without a terminal to ask on, nothing is
+/// deleted.
+pub fn confirm(plan: &MirrorPlan, yes: bool) -> bool {
+    if plan.files.is_empty() && plan.dirs.is_empty() {
+        return false;
+    }
+
+    println!("Mirror will delete:");
+    plan.files
+        .iter()
+        .for_each(|path| println!("\t{}", path.to_str().unwrap()));
+    plan.dirs
+        .iter()
+        .for_each(|path| println!("\t{}/", path.to_str().unwrap()));
+
+    ask(
+        &format!(
+            "Delete {} files and {} directories?",
+            plan.files.len(),
+            plan.dirs.len()
+        ),
+        yes,
+        "--yes",
+    )
+}
+
+/// Prints the target copies of albums removed from the source and asks for
+/// confirmation unless `yes` is set, which `--yes` alone doesn't do.
+pub fn confirm_removed_albums(plan: &MirrorPlan, yes: bool) -> bool {
+    let albums = plan
+        .removed_albums
+        .iter()
+        .filter_map(|album| album.target.as_ref())
+        .collect::<Vec<_>>();
+
+    if albums.is_empty() {
+        return false;
+    }
+
+    println!("Albums no longer in the source:");
+    albums
+        .iter()
+        .for_each(|path| println!("\t{}/", path.to_str().unwrap()));
+
+    ask(
+        &format!("Delete these {} albums from the target?", albums.len()),
+        yes,
+        "--delete-removed-albums",
+    )
+}
+
+/// Asks `question` on the terminal, unless `yes`, set by `flag`, already
+/// answered it. Without a terminal to ask on, the answer is no.
+pub fn ask(question: &str, yes: bool, flag: &str) -> bool {
+    if yes {
+        return true;
+    }
+
+    if !std::io::stdin().is_terminal() {
+        warnln!("Not deleting anything: pass {flag} to confirm deletions");
+        return false;
+    }
+
+    print!("{question} [y/N] ");
+    let _ = std::io::stdout().flush();
+
+    let mut answer = String::new();
+    let _ = std::io::stdin().lock().read_line(&mut answer);
+
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// Deletes the files and directories in `plan`, plus, with
+/// `delete_removed_albums`, the target copies of the albums removed from the
+/// source. Albums are forgotten once they have no target copy left. Returns
+/// the number of files and directories removed.
+pub fn apply(
+    plan: &MirrorPlan,
+    library: &LibraryIndex,
+    delete_files: bool,
+    delete_removed_albums: bool,
+) -> (usize, usize) {
+    let mut removed_files = 0;
+    let mut removed_dirs = 0;
+
+    for path in plan.files.iter().filter(|_| delete_files) {
+        match fs::remove_file(path) {
+            Ok(()) => {
+                removed_files += 1;
+                if let Err(err) = library.remove_target_file(path) {
+                    errln!("Failed to update library index: {:?}", err);
+                }
+            }
+            Err(err) => errln!("Failed to remove {}: {:?}", path.to_str().unwrap(), err),
+        }
+    }
+
+    for path in plan.dirs.iter().filter(|_| delete_files) {
+        // Only succeeds if nothing new appeared in the directory meanwhile.
+        match fs::remove_dir(path) {
+            Ok(()) => removed_dirs += 1,
+            Err(err) => errln!("Failed to remove {}: {:?}", path.to_str().unwrap(), err),
+        }
+    }
+
+    for album in &plan.removed_albums {
+        if let Some(album_dir) = &album.target {
+            if !delete_removed_albums {
+                continue;
+            }
+
+            if let Err(err) = fs::remove_dir_all(album_dir) {
+                errln!(
+                    "Failed to remove {}: {:?}",
+                    album_dir.to_str().unwrap(),
+                    err
+                );
+                continue;
+            }
+            removed_dirs += 1;
+
+            // The artist directory goes too if that was its last album.
+            if let Some(artist_dir) = album_dir.parent() {
+                if read_dir_paths(artist_dir).is_empty() && fs::remove_dir(artist_dir).is_ok() {
+                    removed_dirs += 1;
+                }
+            }
+        }
+
+        if let Err(err) = library.remove_album(&album.source) {
+            errln!("Failed to update library index: {:?}", err);
+        }
+    }
+
+    (removed_files, removed_dirs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    struct Library {
+        dir: TempDir,
+        index: LibraryIndex,
+    }
+
+    impl Library {
+        fn new() -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let index = LibraryIndex::open(&dir.path().join("library.db"), false, false).unwrap();
+            fs::create_dir_all(dir.path().join("source")).unwrap();
+            fs::create_dir_all(dir.path().join("target")).unwrap();
+            Self { dir, index }
+        }
+
+        fn source(&self) -> PathBuf {
+            self.dir.path().join("source")
+        }
+
+        fn target(&self) -> PathBuf {
+            self.dir.path().join("target")
+        }
+
+        /// Records the source album at `source` as copied to `target`.
+        fn add_album(&self, source: &str, target: &str, files: &[&str]) -> (PathBuf, PathBuf) {
+            let (source, target) = (self.source().join(source), self.target().join(target));
+            touch(&source, files);
+            touch(&target, files);
+            self.index
+                .record_album(&index::AlbumRecord {
+                    source_path: &source,
+                    artist: "Artist",
+                    album: "Album",
+                    year: None,
+                    musicbrainz_release_id: None,
+                    target_path: Some(&target),
+                    cover_status: index::CoverStatus::Present,
+                    files: &index::list_source_files(&source),
+                })
+                .unwrap();
+            (source, target)
+        }
+
+        fn plan(&self) -> MirrorPlan {
+            plan(&self.index, &self.source(), &self.target(), false).unwrap()
+        }
+    }
+
+    fn touch(dir: &Path, files: &[&str]) {
+        fs::create_dir_all(dir).unwrap();
+        for file in files {
+            fs::write(dir.join(file), "").unwrap();
+        }
+    }
+
+    #[test]
+    fn deletes_files_no_longer_in_the_source() {
+        let library = Library::new();
+        let (_, album_dir) = library.add_album("Artist/Album", "Artist/Album", &["01.flac"]);
+        touch(&album_dir, &["02.flac"]);
+
+        let plan = library.plan();
+        assert_eq!(plan.files, [album_dir.join("02.flac")]);
+        assert!(plan.dirs.is_empty());
+        assert!(plan.removed_albums.is_empty());
+
+        assert_eq!(apply(&plan, &library.index, true, false), (1, 0));
+        assert!(album_dir.join("01.flac").is_file());
+        assert!(!album_dir.join("02.flac").exists());
+    }
+
+    #[test]
+    fn keeps_artwork_the_organizer_wrote() {
+        let library = Library::new();
+        let (_, album_dir) = library.add_album("Artist/Album", "Artist/Album", &["01.flac"]);
+        touch(&album_dir, &["cover.jpg", "Album.nfo"]);
+        touch(album_dir.parent().unwrap(), &["artist.jpg", "backdrop.jpg"]);
+
+        assert!(library.plan().is_empty());
+    }
+
+    #[test]
+    fn removes_dirs_left_empty_unless_artist_art_remains() {
+        let library = Library::new();
+        let (source, album_dir) = library.add_album("A/Album", "A/Album", &["01.flac"]);
+        let (other_source, other_album_dir) = library.add_album("B/Album", "B/Album", &["01.flac"]);
+        touch(album_dir.parent().unwrap(), &["artist.jpg"]);
+        // Renamed in the source, so the target copies are stale.
+        fs::rename(source.join("01.flac"), source.join("1.flac")).unwrap();
+        fs::rename(other_source.join("01.flac"), other_source.join("1.flac")).unwrap();
+
+        let plan = library.plan();
+        assert_eq!(
+            plan.files,
+            [album_dir.join("01.flac"), other_album_dir.join("01.flac")]
+        );
+        let mut dirs = plan.dirs.clone();
+        dirs.sort();
+        assert_eq!(
+            dirs,
+            [
+                album_dir.clone(),
+                other_album_dir.parent().unwrap().to_path_buf(),
+                other_album_dir.clone()
+            ]
+        );
+
+        apply(&plan, &library.index, true, false);
+        assert!(album_dir.parent().unwrap().join("artist.jpg").is_file());
+        assert!(!other_album_dir.parent().unwrap().exists());
+    }
+
+    #[test]
+    fn keeps_files_of_every_album_sharing_a_target_dir() {
+        let library = Library::new();
+        let (_, album_dir) = library.add_album("Artist/CD1", "Artist/Album", &["101.flac"]);
+        library.add_album("Artist/CD2", "Artist/Album", &["201.flac"]);
+        touch(&album_dir, &["301.flac"]);
+
+        assert_eq!(library.plan().files, [album_dir.join("301.flac")]);
+    }
+
+    #[test]
+    fn deletes_removed_albums_only_when_confirmed() {
+        let library = Library::new();
+        let (_, kept_dir) = library.add_album("Artist/Kept", "Artist/Kept", &["01.flac"]);
+        let (source, album_dir) = library.add_album("Artist/Gone", "Artist/Gone", &["01.flac"]);
+        fs::remove_dir_all(&source).unwrap();
+
+        let plan = library.plan();
+        assert!(plan.files.is_empty() && plan.dirs.is_empty());
+        assert_eq!(plan.removed_albums.len(), 1);
+        assert_eq!(plan.removed_albums[0].source, source);
+        assert_eq!(plan.removed_albums[0].target.as_ref(), Some(&album_dir));
+        assert!(!confirm(&plan, true));
+        assert!(confirm_removed_albums(&plan, true));
+
+        // --yes alone confirms `files`, not removed albums.
+        apply(&plan, &library.index, true, false);
+        assert!(album_dir.join("01.flac").is_file());
+        assert!(library.index.album(&source).is_some());
+
+        assert_eq!(apply(&plan, &library.index, true, true), (0, 1));
+        assert!(!album_dir.exists());
+        assert!(kept_dir.is_dir());
+        assert!(library.index.album(&source).is_none());
+    }
+
+    #[test]
+    fn keeps_the_target_of_a_removed_album_sharing_it() {
+        let library = Library::new();
+        let (_, album_dir) = library.add_album("Artist/CD1", "Artist/Album", &["101.flac"]);
+        let (source, _) = library.add_album("Artist/CD2", "Artist/Album", &["201.flac"]);
+        fs::remove_dir_all(&source).unwrap();
+
+        let plan = library.plan();
+        assert_eq!(plan.removed_albums.len(), 1);
+        assert_eq!(plan.removed_albums[0].target, None);
+
+        apply(&plan, &library.index, true, true);
+        assert!(album_dir.join("101.flac").is_file());
+        assert!(library.index.album(&source).is_none());
+    }
+
+    #[test]
+    fn refuses_to_plan_without_a_source() {
+        let library = Library::new();
+        let (source, album_dir) = library.add_album("Artist/Album", "Artist/Album", &["01.flac"]);
+        fs::remove_dir_all(source.parent().unwrap()).unwrap();
+
+        // Empty, as when the drive holding it isn't mounted.
+        assert!(plan(&library.index, &library.source(), &library.target(), false).is_err());
+
+        fs::remove_dir(library.source()).unwrap();
+        assert!(plan(&library.index, &library.source(), &library.target(), false).is_err());
+
+        assert!(album_dir.join("01.flac").is_file());
+    }
+}
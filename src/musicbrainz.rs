@@ -0,0 +1,247 @@
+//! MusicBrainz release lookup used as the cover fallback when Tidal has
+//! nothing for an album.
+
+use reqwest::RequestBuilder;
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::{
+    artwork::{best_match, AlbumQuery, CoverSize},
+    cover_art_archive::{fetch_front_cover, COVER_ART_ARCHIVE_URL},
+    http::HttpClient,
+    matching::similarity,
+};
+
+const RELEASE_SEARCH_URL: &str = "https://musicbrainz.org/ws/2/release/";
+
+#[derive(Deserialize, Debug)]
+struct ReleaseSearch {
+    #[serde(default)]
+    releases: Vec<Release>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct Release {
+    id: String,
+    #[serde(default)]
+    score: u8,
+    title: String,
+    status: Option<String>,
+    date: Option<String>,
+    track_count: Option<u32>,
+    #[serde(default)]
+    artist_credit: Vec<ArtistCredit>,
+    release_group: Option<ReleaseGroup>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ArtistCredit {
+    name: String,
+    joinphrase: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ReleaseGroup {
+    id: String,
+}
+
+/// Extracts an MBID from a tag value. Multi-valued tags are joined with `;`
+/// or `/` by some taggers, in which case the first value is used.
+pub fn parse_mbid(value: &str) -> Option<&str> {
+    let mbid = value.split([';', '/']).next()?.trim();
+
+    let is_mbid = mbid.len() == 36
+        && mbid.chars().enumerate().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        });
+
+    is_mbid.then_some(mbid)
+}
+
+impl Release {
+    fn artist_credit(&self) -> String {
+        self.artist_credit
+            .iter()
+            .map(|credit| {
+                format!(
+                    "{}{}",
+                    credit.name,
+                    credit.joinphrase.as_deref().unwrap_or_default()
+                )
+            })
+            .collect()
+    }
+
+    fn year(&self) -> Option<i32> {
+        self.date.as_ref()?.get(..4)?.parse().ok()
+    }
+
+    /// Weighted match score in the range `0.0..=1.0`.
+    fn match_score(&self, query: &AlbumQuery) -> f64 {
+        let search_score = f64::from(self.score) / 100.0;
+        let artist_score = similarity(&self.artist_credit(), query.artist);
+        let title_score = similarity(&self.title, query.album);
+
+        let track_count_score = match self.track_count {
+            Some(count) if query.track_count > 0 => {
+                let diff = (count as f64 - query.track_count as f64).abs();
+                (1.0 - diff / query.track_count as f64).max(0.0)
+            }
+            _ => 0.5,
+        };
+
+        let year_score = match (self.year(), query.year) {
+            (Some(a), Some(b)) if a == b => 1.0,
+            (Some(a), Some(b)) if (a - b).abs() == 1 => 0.5,
+            (Some(_), Some(_)) => 0.0,
+            _ => 0.5,
+        };
+
+        let status_score = match self.status.as_deref() {
+            Some("Official") => 1.0,
+            Some("Bootleg") | Some("Pseudo-Release") => 0.0,
+            _ => 0.5,
+        };
+
+        search_score * 0.2
+            + artist_score * 0.25
+            + title_score * 0.3
+            + track_count_score * 0.15
+            + year_score * 0.05
+            + status_score * 0.05
+    }
+}
+
+/// Strips characters that have a meaning in the Lucene query syntax.
+fn escape_query_value(value: &str) -> String {
+    value.chars().filter(|c| !matches!(c, '"' | '\\')).collect()
+}
+
+fn search_request(client: &HttpClient, query: &AlbumQuery<'_>) -> RequestBuilder {
+    let lucene_query = format!(
+        "artist:\"{}\" AND release:\"{}\"",
+        escape_query_value(query.artist),
+        escape_query_value(query.album),
+    );
+    debugln!("Searching MusicBrainz releases for {lucene_query}");
+
+    client.get(RELEASE_SEARCH_URL).query(&[
+        ("query", lucene_query.as_str()),
+        ("fmt", "json"),
+        ("limit", "25"),
+    ])
+}
+
+fn cover_art_urls(release_id: Option<&str>, release_group_id: Option<&str>) -> Vec<String> {
+    release_id
+        .map(|id| format!("{COVER_ART_ARCHIVE_URL}/release/{id}"))
+        .into_iter()
+        .chain(release_group_id.map(|id| format!("{COVER_ART_ARCHIVE_URL}/release-group/{id}")))
+        .collect()
+}
+
+async fn fetch_first_cover(
+    client: &HttpClient,
+    cover_urls: Vec<String>,
+    cover_size: CoverSize,
+    album_path: &Path,
+) -> bool {
+    for request_url in cover_urls {
+        if fetch_front_cover(client, &request_url, cover_size, album_path).await {
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Saves the album's cover into `album_path`. When the tags carry MusicBrainz
+/// IDs they're used directly; otherwise MusicBrainz is searched, the best
+/// scoring release is picked, and the release group's cover is used when the
+/// chosen release has none. Returns whether a cover was saved.
+pub async fn fetch_album_cover(
+    client: &HttpClient,
+    query: &AlbumQuery<'_>,
+    cover_size: CoverSize,
+    album_path: &Path,
+) -> bool {
+    if query.release_id.is_some() || query.release_group_id.is_some() {
+        outln!(
+            "Using exact MusicBrainz ID match from tags (release: {}, release group: {})",
+            query.release_id.unwrap_or("none"),
+            query.release_group_id.unwrap_or("none"),
+        );
+        let cover_urls = cover_art_urls(query.release_id, query.release_group_id);
+        return fetch_first_cover(client, cover_urls, cover_size, album_path).await;
+    }
+
+    let request = search_request(client, query);
+
+    let Some(search) = client
+        .get_json::<ReleaseSearch>("musicbrainz", request.try_clone().unwrap())
+        .await
+    else {
+        return false;
+    };
+
+    let Some(release) = best_match(
+        client,
+        "musicbrainz",
+        &request,
+        "MusicBrainz release",
+        &search.releases,
+        |release| release.match_score(query),
+        |release| {
+            format!(
+                "{} '{}' by '{}'",
+                release.id,
+                release.title,
+                release.artist_credit()
+            )
+        },
+    ) else {
+        return false;
+    };
+
+    let cover_urls = cover_art_urls(
+        Some(&release.id),
+        release
+            .release_group
+            .as_ref()
+            .map(|group| group.id.as_str()),
+    );
+    fetch_first_cover(client, cover_urls, cover_size, album_path).await
+}
+
+#[derive(Deserialize, Debug)]
+struct ReleaseLookup {
+    #[serde(default)]
+    media: Vec<Medium>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct Medium {
+    track_count: usize,
+}
+
+/// The number of tracks on each medium (disc) of the release `release_id`.
+pub async fn fetch_track_counts(client: &HttpClient, release_id: &str) -> Option<Vec<usize>> {
+    let request = client
+        .get(format!("{RELEASE_SEARCH_URL}{release_id}"))
+        .query(&[("fmt", "json")]);
+
+    let release = client
+        .get_json::<ReleaseLookup>("musicbrainz", request)
+        .await?;
+
+    (!release.media.is_empty()).then(|| {
+        release
+            .media
+            .iter()
+            .map(|medium| medium.track_count)
+            .collect()
+    })
+}
@@ -0,0 +1,176 @@
+//! Naming templates for the artist and album directories in the target.
+//!
+//! `{artist}`, `{album}`, `{year}` and `{album_dir}` (the source directory's
+//! name) are replaced with the album's values, with any `/` in them turned
+//! into `-`. `{year}` is empty when the tags have none, and separators left
+//! dangling at either end are trimmed, so `{year} - {album}` becomes just the
+//! album title.
+//!
+//! The default templates leave the names untouched, so libraries synced
+//! before templates existed keep their layout.
+
+pub const DEFAULT_ARTIST_DIR: &str = "{artist}";
+pub const DEFAULT_ALBUM_DIR: &str = "{album_dir}";
+
+const FIELDS: [&str; 4] = ["artist", "album", "year", "album_dir"];
+
+pub struct Fields<'a> {
+    pub artist: &'a str,
+    pub album: &'a str,
+    pub year: Option<i32>,
+    pub album_dir: &'a str,
+}
+
+#[derive(Clone, Debug)]
+pub struct Naming {
+    pub artist_dir: String,
+    pub album_dir: String,
+}
+
+impl Default for Naming {
+    fn default() -> Self {
+        Self {
+            artist_dir: DEFAULT_ARTIST_DIR.to_string(),
+            album_dir: DEFAULT_ALBUM_DIR.to_string(),
+        }
+    }
+}
+
+/// Checks that `template` names a single directory and only uses known
+/// fields.
+pub fn validate(template: &str) -> Result<(), String> {
+    if template.contains('/') {
+        return Err(format!(
+            "template '{template}' names more than one directory"
+        ));
+    }
+
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            return Err(format!("unclosed '{{' in template '{template}'"));
+        };
+        let field = &rest[start + 1..start + end];
+        if !FIELDS.contains(&field) {
+            return Err(format!(
+                "unknown field '{{{field}}}' in template '{template}', expected one of {}",
+                FIELDS.map(|field| format!("{{{field}}}")).join(", ")
+            ));
+        }
+        rest = &rest[start + end + 1..];
+    }
+
+    Ok(())
+}
+
+/// Renders `template`, or falls back to `fallback` if that leaves nothing
+/// usable.
+fn render(template: &str, fields: &Fields, fallback: &str) -> String {
+    let year = fields.year.map(|year| year.to_string()).unwrap_or_default();
+    let mut rendered = template.to_string();
+
+    for (field, value) in [
+        ("artist", fields.artist),
+        ("album", fields.album),
+        ("year", year.as_str()),
+        ("album_dir", fields.album_dir),
+    ] {
+        rendered = rendered.replace(&format!("{{{field}}}"), &value.replace('/', "-"));
+    }
+
+    let trimmed = rendered.trim_matches(|c: char| c.is_whitespace() || c == '-');
+    if trimmed.is_empty() || trimmed == "." || trimmed == ".." {
+        fallback.replace('/', "-")
+    } else {
+        trimmed.to_string()
+    }
+}
+
+impl Naming {
+    pub fn artist_dir(&self, fields: &Fields) -> String {
+        if self.artist_dir == DEFAULT_ARTIST_DIR {
+            return fields.artist.to_string();
+        }
+        render(&self.artist_dir, fields, fields.artist)
+    }
+
+    pub fn album_dir(&self, fields: &Fields) -> String {
+        if self.album_dir == DEFAULT_ALBUM_DIR {
+            return fields.album_dir.to_string();
+        }
+        render(&self.album_dir, fields, fields.album_dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALBUM: Fields = Fields {
+        artist: "AC/DC",
+        album: "Back in Black",
+        year: Some(1980),
+        album_dir: "-Back in Black-",
+    };
+
+    fn naming(artist_dir: &str, album_dir: &str) -> Naming {
+        Naming {
+            artist_dir: artist_dir.to_string(),
+            album_dir: album_dir.to_string(),
+        }
+    }
+
+    #[test]
+    fn default_templates_leave_names_untouched() {
+        let naming = Naming::default();
+
+        assert_eq!(naming.artist_dir(&ALBUM), "AC/DC");
+        assert_eq!(naming.album_dir(&ALBUM), "-Back in Black-");
+    }
+
+    #[test]
+    fn renders_fields_without_slashes() {
+        let naming = naming("{artist}", "{year} - {album} [{album_dir}]");
+
+        assert_eq!(
+            naming.album_dir(&ALBUM),
+            "1980 - Back in Black [-Back in Black-]"
+        );
+        assert_eq!(render("{artist}", &ALBUM, "x"), "AC-DC");
+    }
+
+    #[test]
+    fn trims_separators_left_by_empty_fields() {
+        let fields = Fields {
+            year: None,
+            ..ALBUM
+        };
+
+        assert_eq!(
+            naming("", "{year} - {album}").album_dir(&fields),
+            "Back in Black"
+        );
+    }
+
+    #[test]
+    fn falls_back_when_nothing_usable_is_left() {
+        let fields = Fields {
+            album: "..",
+            year: None,
+            ..ALBUM
+        };
+
+        assert_eq!(naming("", "{year}").album_dir(&fields), "-Back in Black-");
+        assert_eq!(naming("", "{album}").album_dir(&fields), "-Back in Black-");
+        assert_eq!(naming("{year}", "").artist_dir(&fields), "AC-DC");
+    }
+
+    #[test]
+    fn validates_templates() {
+        assert!(validate("{year} - {album}").is_ok());
+        assert!(validate("{artist}/{album}").is_err());
+        assert!(validate("{album").is_err());
+        assert!(validate("{title}").is_err());
+    }
+}
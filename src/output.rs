@@ -0,0 +1,267 @@
+//! Leveled console output that stays grouped per album when albums are
+//! processed concurrently.
+//!
+//! [`errln!`], [`warnln!`], [`outln!`] and [`debugln!`] log at the error,
+//! warning, info and debug levels. What gets through is decided per module by
+//! `-v`/`-q` and `--log-filter`, except for errors, which always do. Errors and
+//! warnings go to stderr, the rest to stdout, as plain lines or, with
+//! `--log-format json`, one JSON object each.
+//!
+//! Inside [`grouped`], lines are buffered and flushed together once the album
+//! is done. Warnings and errors are also recorded in the album's report, if
+//! any, whatever the level.
+
+use clap::ValueEnum;
+use std::{
+    future::Future,
+    io::Write,
+    str::FromStr,
+    sync::{Arc, Mutex, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+tokio::task_local! {
+    static ALBUM_OUTPUT: Arc<Mutex<Vec<(bool, String)>>>;
+}
+
+static CONFIG: OnceLock<LogConfig> = OnceLock::new();
+
+macro_rules! errln {
+    ($($arg:tt)*) => {
+        $crate::output::log($crate::output::Level::Error, module_path!(), format!($($arg)*))
+    };
+}
+
+macro_rules! warnln {
+    ($($arg:tt)*) => {
+        $crate::output::log($crate::output::Level::Warn, module_path!(), format!($($arg)*))
+    };
+}
+
+macro_rules! outln {
+    ($($arg:tt)*) => {
+        $crate::output::log($crate::output::Level::Info, module_path!(), format!($($arg)*))
+    };
+}
+
+macro_rules! debugln {
+    ($($arg:tt)*) => {
+        $crate::output::log($crate::output::Level::Debug, module_path!(), format!($($arg)*))
+    };
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+
+    /// The level `-v` and `-q` flags move the default of info to.
+    pub fn from_verbosity(verbose: u8, quiet: u8) -> Level {
+        match 2 + i16::from(verbose) - i16::from(quiet) {
+            i16::MIN..=0 => Level::Error,
+            1 => Level::Warn,
+            2 => Level::Info,
+            3.. => Level::Debug,
+        }
+    }
+}
+
+/// Parses a level, or "off" as `None`.
+fn parse_level(value: &str) -> Result<Option<Level>, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "off" => Ok(None),
+        "error" => Ok(Some(Level::Error)),
+        "warn" | "warning" => Ok(Some(Level::Warn)),
+        "info" => Ok(Some(Level::Info)),
+        "debug" => Ok(Some(Level::Debug)),
+        _ => Err(format!(
+            "unknown level '{value}', expected off, error, warn, info or debug"
+        )),
+    }
+}
+
+/// `--log-filter`: comma-separated `<module>=<level>` directives, plus
+/// optionally a bare `<level>` for every other module, e.g.
+/// `warn,http=debug,discogs=off`. `off` still lets errors through.
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    default: Option<Option<Level>>,
+    modules: Vec<(String, Option<Level>)>,
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut filter = Filter::default();
+
+        for directive in value.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((module, level)) => filter
+                    .modules
+                    .push((module.trim().to_string(), parse_level(level.trim())?)),
+                None => filter.default = Some(parse_level(directive)?),
+            }
+        }
+
+        Ok(filter)
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug)]
+pub struct LogConfig {
+    pub level: Level,
+    pub filter: Filter,
+    pub format: LogFormat,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: Level::Info,
+            filter: Filter::default(),
+            format: LogFormat::Text,
+        }
+    }
+}
+
+impl LogConfig {
+    /// The most verbose level logged for `module`. The most specific
+    /// directive wins, and `index` also covers `index::schema`.
+    fn max_level(&self, module: &str) -> Level {
+        self.filter
+            .modules
+            .iter()
+            .filter(|(name, _)| {
+                module == name
+                    || module
+                        .strip_prefix(name.as_str())
+                        .is_some_and(|rest| rest.starts_with("::"))
+            })
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, level)| *level)
+            .or(self.filter.default)
+            .unwrap_or(Some(self.level))
+            .unwrap_or(Level::Error)
+    }
+}
+
+/// Sets how output is filtered and formatted. Until then, info and up is
+/// logged as plain text.
+pub fn init(config: LogConfig) {
+    let _ = CONFIG.set(config);
+}
+
+/// The module a line was logged from, relative to the crate ("main" for the
+/// crate root).
+fn module_name(module_path: &str) -> &str {
+    module_path
+        .split_once("::")
+        .map_or("main", |(_, module)| module)
+}
+
+pub fn log(level: Level, module_path: &str, message: String) {
+    match level {
+        Level::Error => crate::report::record(|report| report.errors.push(message.clone())),
+        Level::Warn => crate::report::record(|report| report.warnings.push(message.clone())),
+        Level::Info | Level::Debug => {}
+    }
+
+    let config = CONFIG.get_or_init(LogConfig::default);
+    let module = module_name(module_path);
+
+    if level > config.max_level(module) {
+        return;
+    }
+
+    let line = match config.format {
+        LogFormat::Text => message,
+        LogFormat::Json => serde_json::json!({
+            "time": SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_millis() as u64),
+            "level": level.name(),
+            "module": module,
+            "message": message,
+        })
+        .to_string(),
+    };
+
+    write_line(level <= Level::Warn, line);
+}
+
+fn write_line(is_error: bool, line: String) {
+    let mut line = Some(line);
+
+    let _ = ALBUM_OUTPUT.try_with(|output| {
+        output
+            .lock()
+            .unwrap()
+            .push((is_error, line.take().unwrap()));
+    });
+
+    if let Some(line) = line {
+        crate::progress::suspend(|| print_line(is_error, &line));
+    }
+}
+
+fn print_line(is_error: bool, line: &str) {
+    if is_error {
+        eprintln!("{line}");
+    } else {
+        println!("{line}");
+    }
+}
+
+/// Runs `future` with its output buffered, then prints it in one go.
+pub async fn grouped<F: Future>(future: F) -> F::Output {
+    ALBUM_OUTPUT
+        .scope(Arc::default(), async move {
+            let value = future.await;
+
+            let lines = ALBUM_OUTPUT.with(|output| std::mem::take(&mut *output.lock().unwrap()));
+            crate::progress::suspend(|| {
+                let stdout = std::io::stdout().lock();
+                let stderr = std::io::stderr().lock();
+                for (is_error, line) in lines {
+                    print_line(is_error, &line);
+                }
+                drop((stdout, stderr));
+                let _ = std::io::stdout().flush();
+            });
+
+            value
+        })
+        .await
+}
+
+/// Wraps `f` so that, run on another thread, its output still goes to the
+/// buffer of the album being processed, if any.
+pub fn carry<R>(f: impl FnOnce() -> R) -> impl FnOnce() -> R {
+    let output = ALBUM_OUTPUT.try_with(Arc::clone).ok();
+
+    move || match output {
+        Some(output) => ALBUM_OUTPUT.sync_scope(output, f),
+        None => f(),
+    }
+}
@@ -0,0 +1,271 @@
+//! Progress of a run over the source.
+//!
+//! When stdout is a terminal, a display below the log shows how many albums
+//! are done, the bytes copied and the throughput, the albums being processed
+//! and the provider lookups in flight. It's redrawn in place a few times a
+//! second, and cleared and redrawn around every line of output. Otherwise a
+//! plain line is logged as each album finishes.
+//!
+//! Everything is a no-op until [`start`] is called.
+
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+
+static PROGRESS: OnceLock<Progress> = OnceLock::new();
+
+/// Serializes writes to the terminal and remembers how many display lines
+/// are currently on it. Locked before stdout/stderr, never after.
+static DISPLAY: Mutex<usize> = Mutex::new(0);
+
+struct Progress {
+    total: usize,
+    done: AtomicUsize,
+    bytes: AtomicU64,
+    start: Instant,
+    live: bool,
+    finished: AtomicBool,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    albums: Vec<PathBuf>,
+    lookups: BTreeMap<String, usize>,
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// The last two components of `path`: `<artist>/<album>`.
+fn album_name(path: &Path) -> String {
+    let mut components = path.components().rev().take(2).collect::<Vec<_>>();
+    components.reverse();
+    components
+        .iter()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+impl Progress {
+    fn summary(&self) -> String {
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let throughput = if elapsed > 0.0 {
+            (bytes as f64 / elapsed) as u64
+        } else {
+            0
+        };
+
+        format!(
+            "{}/{} albums, {} copied ({}/s)",
+            self.done.load(Ordering::Relaxed),
+            self.total,
+            format_bytes(bytes),
+            format_bytes(throughput)
+        )
+    }
+
+    fn render(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        let mut lines = vec![format!("[{}]", self.summary())];
+
+        lines.extend(
+            state
+                .albums
+                .iter()
+                .map(|album| format!("  {}", album_name(album))),
+        );
+
+        if !state.lookups.is_empty() {
+            let lookups = state
+                .lookups
+                .iter()
+                .map(|(provider, count)| match count {
+                    1 => provider.clone(),
+                    _ => format!("{provider} x{count}"),
+                })
+                .collect::<Vec<_>>();
+            lines.push(format!("  looking up: {}", lookups.join(", ")));
+        }
+
+        lines
+    }
+
+    fn update(&self, update: impl FnOnce(&mut State)) {
+        update(&mut self.state.lock().unwrap());
+    }
+}
+
+/// Width the display's lines are cut to, so they don't wrap.
+fn terminal_width() -> usize {
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .unwrap_or(80)
+}
+
+fn clear(drawn: &mut usize, stdout: &mut impl Write) {
+    if *drawn > 0 {
+        let _ = write!(stdout, "\x1b[{}A\r\x1b[J", *drawn);
+        *drawn = 0;
+    }
+}
+
+fn draw(progress: &Progress, drawn: &mut usize, stdout: &mut impl Write) {
+    if progress.finished.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let width = terminal_width().saturating_sub(1);
+    let lines = progress.render();
+    for line in &lines {
+        let line = line.chars().take(width).collect::<String>();
+        let _ = writeln!(stdout, "{line}\x1b[K");
+    }
+    *drawn = lines.len();
+    let _ = stdout.flush();
+}
+
+fn live_progress() -> Option<&'static Progress> {
+    PROGRESS.get().filter(|progress| progress.live)
+}
+
+/// Starts tracking a run over `total` albums, with the live display if
+/// `live`.
+pub fn start(total: usize, live: bool) {
+    let progress = Progress {
+        total,
+        done: AtomicUsize::new(0),
+        bytes: AtomicU64::new(0),
+        start: Instant::now(),
+        live,
+        finished: AtomicBool::new(false),
+        state: Mutex::new(State::default()),
+    };
+    if PROGRESS.set(progress).is_err() || !live {
+        return;
+    }
+
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(REDRAW_INTERVAL);
+        while let Some(progress) = live_progress().filter(|p| !p.finished.load(Ordering::Relaxed)) {
+            interval.tick().await;
+            let mut drawn = DISPLAY.lock().unwrap();
+            let mut stdout = std::io::stdout().lock();
+            clear(&mut drawn, &mut stdout);
+            draw(progress, &mut drawn, &mut stdout);
+        }
+    });
+}
+
+/// Removes the display for good, before the run's summary is printed.
+pub fn finish() {
+    if let Some(progress) = live_progress() {
+        let mut drawn = DISPLAY.lock().unwrap();
+        progress.finished.store(true, Ordering::Relaxed);
+        clear(&mut drawn, &mut std::io::stdout().lock());
+    }
+}
+
+/// Runs `write` with the display cleared from the terminal, so what it
+/// writes ends up above the display.
+pub fn suspend<R>(write: impl FnOnce() -> R) -> R {
+    let mut drawn = DISPLAY.lock().unwrap();
+
+    let Some(progress) = live_progress() else {
+        return write();
+    };
+
+    clear(&mut drawn, &mut std::io::stdout().lock());
+    let value = write();
+    draw(progress, &mut drawn, &mut std::io::stdout().lock());
+
+    value
+}
+
+pub fn add_bytes(bytes: u64) {
+    if let Some(progress) = PROGRESS.get() {
+        progress.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+/// An album being processed. Counts as done once dropped.
+pub struct AlbumGuard(Option<PathBuf>);
+
+pub fn album(dir: &Path) -> AlbumGuard {
+    let Some(progress) = PROGRESS.get() else {
+        return AlbumGuard(None);
+    };
+
+    progress.update(|state| state.albums.push(dir.to_path_buf()));
+    AlbumGuard(Some(dir.to_path_buf()))
+}
+
+impl Drop for AlbumGuard {
+    fn drop(&mut self) {
+        let (Some(dir), Some(progress)) = (self.0.take(), PROGRESS.get()) else {
+            return;
+        };
+
+        progress.update(|state| state.albums.retain(|album| *album != dir));
+        progress.done.fetch_add(1, Ordering::Relaxed);
+
+        if !progress.live {
+            outln!("Progress: {}", progress.summary());
+        }
+    }
+}
+
+/// A provider request in flight.
+pub struct LookupGuard(Option<String>);
+
+pub fn lookup(provider: &str) -> LookupGuard {
+    let Some(progress) = live_progress() else {
+        return LookupGuard(None);
+    };
+
+    progress.update(|state| *state.lookups.entry(provider.to_string()).or_default() += 1);
+    LookupGuard(Some(provider.to_string()))
+}
+
+impl Drop for LookupGuard {
+    fn drop(&mut self) {
+        let (Some(provider), Some(progress)) = (self.0.take(), live_progress()) else {
+            return;
+        };
+
+        progress.update(|state| {
+            if let Some(count) = state.lookups.get_mut(&provider) {
+                *count -= 1;
+                if *count == 0 {
+                    state.lookups.remove(&provider);
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,518 @@
+//! Stream properties of audio files (codec, duration, sample rate, bit
+//! depth, bitrate), read from their headers without decoding any audio, and
+//! the policy for an album arriving in a different quality than the copy
+//! already in the target.
+
+use clap::ValueEnum;
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+    time::Duration,
+};
+
+use crate::{
+    binary::{
+        id3v2_size, read_u16_be, read_u16_le, read_u32_be, read_u32_le, read_u64_be, read_u64_le,
+    },
+    formats::{self, AudioFormat},
+    raw_tags,
+};
+
+#[derive(Clone, Debug)]
+pub struct TrackQuality {
+    pub codec: &'static str,
+    pub lossless: bool,
+    pub duration: Option<Duration>,
+    pub sample_rate: Option<u32>,
+    pub bits_per_sample: Option<u32>,
+    /// Average bitrate in kbps.
+    pub bitrate: Option<u32>,
+}
+
+impl AudioFormat {
+    /// The codec files of this format usually hold.
+    fn default_codec(self) -> &'static str {
+        match self {
+            AudioFormat::Flac => "FLAC",
+            AudioFormat::Mp3 => "MP3",
+            AudioFormat::Mp4 => "AAC",
+            AudioFormat::Vorbis => "Vorbis",
+            AudioFormat::Opus => "Opus",
+            AudioFormat::Wav | AudioFormat::Aiff => "PCM",
+            AudioFormat::WavPack => "WavPack",
+            AudioFormat::Ape => "APE",
+            AudioFormat::Dsf | AudioFormat::Dff => "DSD",
+        }
+    }
+
+    fn is_lossless(self) -> bool {
+        !matches!(
+            self,
+            AudioFormat::Mp3 | AudioFormat::Mp4 | AudioFormat::Vorbis | AudioFormat::Opus
+        )
+    }
+}
+
+fn seconds(samples: u64, sample_rate: u32) -> Option<Duration> {
+    (sample_rate > 0).then(|| Duration::from_secs_f64(samples as f64 / f64::from(sample_rate)))
+}
+
+/// Reads the properties of the audio file at `path`. Whatever can't be
+/// determined for its format is left `None`.
+pub fn inspect(path: &Path, format: AudioFormat) -> TrackQuality {
+    let mut quality = TrackQuality {
+        codec: format.default_codec(),
+        lossless: format.is_lossless(),
+        duration: None,
+        sample_rate: None,
+        bits_per_sample: None,
+        bitrate: None,
+    };
+
+    let read = match format {
+        AudioFormat::Flac => read_flac(path, &mut quality),
+        AudioFormat::Wav => read_wav(path, &mut quality),
+        AudioFormat::Mp3 => read_mp3(path, &mut quality),
+        AudioFormat::Aiff => read_aiff(path, &mut quality),
+        AudioFormat::Vorbis | AudioFormat::Opus => read_ogg(path, &mut quality),
+        AudioFormat::Mp4 => read_mp4(path, &mut quality),
+        AudioFormat::WavPack => read_wavpack(path, &mut quality),
+        AudioFormat::Dsf => read_dsf(path, &mut quality),
+        AudioFormat::Ape | AudioFormat::Dff => Ok(()),
+    };
+
+    if let Err(err) = read {
+        errln!(
+            "Failed to read stream info from {}: {:?}",
+            path.display(),
+            err
+        );
+    }
+
+    if quality.bitrate.is_none() {
+        if let (Some(duration), Ok(metadata)) = (quality.duration, fs::metadata(path)) {
+            if !duration.is_zero() {
+                quality.bitrate =
+                    Some((metadata.len() as f64 * 8.0 / duration.as_secs_f64() / 1000.0) as u32);
+            }
+        }
+    }
+
+    quality
+}
+
+fn read_flac(path: &Path, quality: &mut TrackQuality) -> std::io::Result<()> {
+    let reader = claxon::FlacReader::open(path).map_err(std::io::Error::other)?;
+    let streaminfo = reader.streaminfo();
+
+    quality.sample_rate = Some(streaminfo.sample_rate);
+    quality.bits_per_sample = Some(streaminfo.bits_per_sample);
+    quality.duration = streaminfo
+        .samples
+        .map(|samples| Duration::from_secs_f64(samples as f64 / streaminfo.sample_rate as f64));
+
+    Ok(())
+}
+
+fn read_wav(path: &Path, quality: &mut TrackQuality) -> std::io::Result<()> {
+    let mut file = File::open(path)?;
+    let mut header = [0u8; 12];
+    file.read_exact(&mut header)?;
+
+    let mut byte_rate = None;
+
+    loop {
+        let mut chunk = [0u8; 8];
+        if file.read_exact(&mut chunk).is_err() {
+            break;
+        }
+        let len = u64::from(read_u32_le(&chunk, 4).unwrap());
+
+        match &chunk[..4] {
+            b"fmt " => {
+                let mut fmt = vec![0u8; len.min(40) as usize];
+                file.read_exact(&mut fmt)?;
+                file.seek(SeekFrom::Current((len - fmt.len() as u64) as i64))?;
+
+                quality.sample_rate = read_u32_le(&fmt, 4);
+                byte_rate = read_u32_le(&fmt, 8).filter(|rate| *rate > 0);
+                quality.bits_per_sample = read_u16_le(&fmt, 14).map(u32::from);
+            }
+            b"data" => {
+                if let Some(byte_rate) = byte_rate {
+                    quality.duration = Some(Duration::from_secs_f64(len as f64 / byte_rate as f64));
+                    quality.bitrate = Some(byte_rate * 8 / 1000);
+                }
+                break;
+            }
+            _ => {
+                file.seek(SeekFrom::Current((len + len % 2) as i64))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// MPEG-1 layer III bitrates in kbps; MPEG-2/2.5 use the second row.
+const MP3_BITRATES: [[u32; 15]; 2] = [
+    [
+        0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ],
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+];
+const MP3_SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000];
+
+/// Reads the first MP3 frame header, and the frame count from a Xing/Info or
+/// VBRI header if there is one. Without a frame count the file is assumed to
+/// be CBR.
+fn read_mp3(path: &Path, quality: &mut TrackQuality) -> std::io::Result<()> {
+    let mut file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+
+    let mut start = 0;
+    let mut id3 = [0u8; 10];
+    if file.read_exact(&mut id3).is_ok() && &id3[..3] == b"ID3" {
+        start = 10 + id3v2_size(&id3);
+    }
+
+    file.seek(SeekFrom::Start(start))?;
+    let mut data = vec![0u8; 8192];
+    let len = file.read(&mut data)?;
+    data.truncate(len);
+
+    let Some(offset) = data
+        .windows(2)
+        .position(|bytes| bytes[0] == 0xff && bytes[1] & 0xe0 == 0xe0)
+    else {
+        return Ok(());
+    };
+    let frame = &data[offset..];
+    let Some(&[_, b1, b2, b3]) = frame.get(..4) else {
+        return Ok(());
+    };
+
+    let mpeg1 = b1 & 0x18 == 0x18;
+    let version_divisor = match b1 & 0x18 {
+        0x18 => 1,
+        0x10 => 2,
+        _ => 4,
+    };
+    let Some(bitrate) = MP3_BITRATES[usize::from(!mpeg1)]
+        .get(usize::from(b2 >> 4))
+        .copied()
+        .filter(|bitrate| *bitrate > 0)
+    else {
+        return Ok(());
+    };
+    let Some(sample_rate) = MP3_SAMPLE_RATES
+        .get(usize::from((b2 >> 2) & 0x3))
+        .map(|rate| rate / version_divisor)
+    else {
+        return Ok(());
+    };
+    quality.sample_rate = Some(sample_rate);
+
+    let mono = b3 >> 6 == 3;
+    let side_info = match (mpeg1, mono) {
+        (true, false) => 32,
+        (true, true) | (false, false) => 17,
+        (false, true) => 9,
+    };
+    let samples_per_frame = if mpeg1 { 1152 } else { 576 };
+
+    let xing = frame
+        .get(4 + side_info..8 + side_info)
+        .filter(|tag| *tag == b"Xing" || *tag == b"Info")
+        .and_then(|_| {
+            let flags = read_u32_be(frame, 8 + side_info)?;
+            (flags & 1 == 1).then(|| read_u32_be(frame, 12 + side_info))?
+        });
+    let vbri = frame
+        .get(36..40)
+        .filter(|tag| *tag == b"VBRI")
+        .and_then(|_| read_u32_be(frame, 50));
+
+    let audio_len = file_len - start - offset as u64;
+    match xing.or(vbri) {
+        Some(frames) => {
+            let seconds = f64::from(frames) * f64::from(samples_per_frame) / f64::from(sample_rate);
+            quality.duration = Some(Duration::from_secs_f64(seconds));
+        }
+        None => {
+            quality.duration = Some(Duration::from_secs_f64(
+                audio_len as f64 * 8.0 / (f64::from(bitrate) * 1000.0),
+            ));
+            quality.bitrate = Some(bitrate);
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the `COMM` chunk, whose sample rate is an 80-bit float.
+fn read_aiff(path: &Path, quality: &mut TrackQuality) -> std::io::Result<()> {
+    let mut file = File::open(path)?;
+    let mut header = [0u8; 12];
+    file.read_exact(&mut header)?;
+
+    loop {
+        let mut chunk = [0u8; 8];
+        if file.read_exact(&mut chunk).is_err() {
+            return Ok(());
+        }
+        let len = u64::from(read_u32_be(&chunk, 4).unwrap());
+
+        if &chunk[..4] != b"COMM" {
+            file.seek(SeekFrom::Current((len + len % 2) as i64))?;
+            continue;
+        }
+
+        let mut comm = [0u8; 18];
+        file.read_exact(&mut comm)?;
+
+        let frames = read_u32_be(&comm, 2).unwrap();
+        let exponent = i32::from(read_u16_be(&comm, 8).unwrap() & 0x7fff) - 16383 - 63;
+        let mantissa = read_u64_be(&comm, 10).unwrap();
+        let sample_rate = (mantissa as f64 * 2f64.powi(exponent)).round() as u32;
+
+        quality.bits_per_sample = read_u16_be(&comm, 6).map(u32::from);
+        quality.sample_rate = Some(sample_rate);
+        quality.duration = seconds(u64::from(frames), sample_rate);
+
+        return Ok(());
+    }
+}
+
+/// Reads the identification header of an Ogg Vorbis or Opus stream, and the
+/// duration from the granule position of the last page.
+fn read_ogg(path: &Path, quality: &mut TrackQuality) -> std::io::Result<()> {
+    let mut file = File::open(path)?;
+    let mut first_page = [0u8; 64];
+    let len = file.read(&mut first_page)?;
+    let first_page = &first_page[..len];
+
+    let Some(segments) = first_page.get(26) else {
+        return Ok(());
+    };
+    let packet = &first_page[(27 + usize::from(*segments)).min(first_page.len())..];
+
+    let file_len = file.metadata()?.len();
+    let tail_len = file_len.min(65536);
+    file.seek(SeekFrom::Start(file_len - tail_len))?;
+    let mut tail = vec![0u8; tail_len as usize];
+    file.read_exact(&mut tail)?;
+    let granule = tail
+        .windows(4)
+        .rposition(|bytes| bytes == b"OggS")
+        .and_then(|offset| read_u64_le(&tail, offset + 6));
+
+    if packet.starts_with(b"OpusHead") {
+        let pre_skip = read_u16_le(packet, 10).unwrap_or(0);
+        // Opus always decodes at 48 kHz; this is the rate of the original.
+        quality.sample_rate = read_u32_le(packet, 12).filter(|rate| *rate > 0);
+        quality.duration =
+            granule.and_then(|granule| seconds(granule.saturating_sub(u64::from(pre_skip)), 48000));
+    } else if packet.starts_with(b"\x01vorbis") {
+        let sample_rate = read_u32_le(packet, 12).unwrap_or(0);
+        quality.sample_rate = Some(sample_rate).filter(|rate| *rate > 0);
+        quality.duration = granule.and_then(|granule| seconds(granule, sample_rate));
+    }
+
+    Ok(())
+}
+
+/// Reads the duration from `mvhd` and the codec, bit depth and sample rate
+/// from the first audio sample description.
+fn read_mp4(path: &Path, quality: &mut TrackQuality) -> std::io::Result<()> {
+    let mut file = File::open(path)?;
+    let Some(moov) = raw_tags::read_mp4_moov(&mut file)? else {
+        return Ok(());
+    };
+
+    if let Some(mvhd) = raw_tags::find_mp4_box(&moov, b"mvhd") {
+        let (timescale, duration) = match mvhd.first() {
+            Some(1) => (read_u32_be(mvhd, 20), read_u64_be(mvhd, 24)),
+            _ => (read_u32_be(mvhd, 12), read_u32_be(mvhd, 16).map(u64::from)),
+        };
+        if let (Some(timescale), Some(duration)) = (timescale, duration) {
+            quality.duration = seconds(duration, timescale);
+        }
+    }
+
+    let sample_entry = raw_tags::mp4_boxes(&moov)
+        .into_iter()
+        .filter(|(box_type, _)| box_type == b"trak")
+        .filter_map(|(_, trak)| {
+            let stsd = [b"mdia", b"minf", b"stbl", b"stsd"]
+                .iter()
+                .try_fold(trak, |parent, child| raw_tags::find_mp4_box(parent, child))?;
+            // Skip version/flags and the entry count.
+            raw_tags::mp4_boxes(stsd.get(8..)?).into_iter().next()
+        })
+        .find(|(entry_type, _)| entry_type == b"mp4a" || entry_type == b"alac");
+
+    if let Some((entry_type, entry)) = sample_entry {
+        quality.sample_rate = read_u32_be(entry, 24).map(|rate| rate >> 16);
+        if &entry_type == b"alac" {
+            quality.codec = "ALAC";
+            quality.lossless = true;
+            quality.bits_per_sample = read_u16_be(entry, 18).map(u32::from);
+        }
+    }
+
+    Ok(())
+}
+
+const WAVPACK_SAMPLE_RATES: [u32; 15] = [
+    6000, 8000, 9600, 11025, 12000, 16000, 22050, 24000, 32000, 44100, 48000, 64000, 88200, 96000,
+    192000,
+];
+
+/// Reads the first WavPack block header. Hybrid files without their
+/// correction file are lossy.
+fn read_wavpack(path: &Path, quality: &mut TrackQuality) -> std::io::Result<()> {
+    let mut header = [0u8; 32];
+    File::open(path)?.read_exact(&mut header)?;
+
+    let flags = read_u32_le(&header, 24).unwrap();
+    let total_samples = read_u32_le(&header, 12).unwrap();
+
+    quality.bits_per_sample = Some((flags & 0x3) * 8 + 8);
+    quality.sample_rate = WAVPACK_SAMPLE_RATES
+        .get(((flags >> 23) & 0xf) as usize)
+        .copied();
+    if let Some(sample_rate) = quality.sample_rate {
+        if total_samples != u32::MAX {
+            quality.duration = seconds(u64::from(total_samples), sample_rate);
+        }
+    }
+
+    if flags & 0x8 != 0 {
+        let correction_file = path.with_extension("wvc");
+        if !correction_file.is_file() {
+            quality.lossless = false;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the `fmt ` chunk following the DSF header.
+fn read_dsf(path: &Path, quality: &mut TrackQuality) -> std::io::Result<()> {
+    let mut header = [0u8; 72];
+    File::open(path)?.read_exact(&mut header)?;
+
+    if &header[28..32] != b"fmt " {
+        return Ok(());
+    }
+
+    let sample_rate = read_u32_le(&header, 56).unwrap();
+    quality.sample_rate = Some(sample_rate);
+    quality.bits_per_sample = read_u32_le(&header, 60);
+    quality.duration = read_u64_le(&header, 64).and_then(|samples| seconds(samples, sample_rate));
+
+    Ok(())
+}
+
+/// The combined properties of an album's tracks.
+#[derive(Clone, Debug)]
+pub struct AlbumQuality {
+    pub tracks: Vec<TrackQuality>,
+}
+
+impl AlbumQuality {
+    pub fn lossless(&self) -> bool {
+        !self.tracks.is_empty() && self.tracks.iter().all(|track| track.lossless)
+    }
+
+    fn min<T: Ord + Copy>(&self, value: impl Fn(&TrackQuality) -> Option<T>) -> Option<T> {
+        self.tracks.iter().filter_map(value).min()
+    }
+
+    /// Sort key, going by the weakest track: lossless beats lossy, then bit
+    /// depth and sample rate for lossless albums and bitrate for lossy ones.
+    pub fn rank(&self) -> (bool, u32, u32) {
+        let lossless = self.lossless();
+        let sample_rate = self.min(|track| track.sample_rate).unwrap_or(0);
+
+        if lossless {
+            (
+                lossless,
+                self.min(|track| track.bits_per_sample).unwrap_or(0),
+                sample_rate,
+            )
+        } else {
+            (
+                lossless,
+                self.min(|track| track.bitrate).unwrap_or(0),
+                sample_rate,
+            )
+        }
+    }
+}
+
+impl fmt::Display for AlbumQuality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut codecs = self
+            .tracks
+            .iter()
+            .map(|track| track.codec)
+            .collect::<Vec<_>>();
+        codecs.sort();
+        codecs.dedup();
+        write!(f, "{}", codecs.join("+"))?;
+
+        if let Some(bits) = self.min(|track| track.bits_per_sample) {
+            write!(f, " {bits}bit")?;
+        }
+        if let Some(rate) = self.min(|track| track.sample_rate) {
+            write!(f, " {:.1}kHz", f64::from(rate) / 1000.0)?;
+        }
+        if let Some(bitrate) = self.min(|track| track.bitrate) {
+            write!(f, " {bitrate}kbps")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the quality of the audio files directly inside `dir`.
+pub fn read_album_quality(dir: &Path) -> AlbumQuality {
+    let mut tracks = fs::read_dir(dir)
+        .map(|entries| entries.filter_map(|p| p.ok()).map(|p| p.path()).collect())
+        .unwrap_or_else(|_| vec![]);
+    tracks.sort();
+
+    AlbumQuality {
+        tracks: tracks
+            .iter()
+            .filter_map(|path| Some(inspect(path, formats::detect(path)?)))
+            .collect(),
+    }
+}
+
+/// What to do when an album is copied into a target directory that already
+/// holds it in a different quality.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QualityPolicy {
+    /// Copy whatever files are missing, mixing formats
+    Merge,
+    /// Lossless replaces lossy; lossy isn't added to lossless
+    PreferLossless,
+    /// Higher quality replaces lower (lossless first, then bit depth, sample rate and bitrate)
+    PreferHigher,
+}
+
+impl QualityPolicy {
+    /// How `incoming` compares with `existing` under this policy.
+    pub fn compare(self, incoming: &AlbumQuality, existing: &AlbumQuality) -> std::cmp::Ordering {
+        match self {
+            QualityPolicy::Merge => std::cmp::Ordering::Equal,
+            QualityPolicy::PreferLossless => incoming.lossless().cmp(&existing.lossless()),
+            QualityPolicy::PreferHigher => incoming.rank().cmp(&existing.rank()),
+        }
+    }
+}
@@ -0,0 +1,546 @@
+//! Reads free-form text tags that `audiotags` doesn't expose (e.g. the
+//! MusicBrainz IDs written by Picard), and all text tags of the formats it
+//! can't read at all.
+//!
+//! Keys are normalized to their uppercase Vorbis comment names, so
+//! `TXXX:MusicBrainz Album Id` in an ID3 tag and the
+//! `----:com.apple.iTunes:MusicBrainz Album Id` MP4 atom both show up as
+//! `MUSICBRAINZ_ALBUMID`.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use id3::TagLike;
+
+use crate::{
+    binary::{id3v2_size, read_u32_le},
+    formats::AudioFormat,
+};
+
+pub type RawTags = HashMap<String, String>;
+
+/// Picard's ID3/MP4 free-form descriptions and their Vorbis comment names.
+const FREEFORM_NAMES: &[(&str, &str)] = &[
+    ("MusicBrainz Album Id", "MUSICBRAINZ_ALBUMID"),
+    ("MusicBrainz Release Group Id", "MUSICBRAINZ_RELEASEGROUPID"),
+    ("MusicBrainz Artist Id", "MUSICBRAINZ_ARTISTID"),
+    ("MusicBrainz Album Artist Id", "MUSICBRAINZ_ALBUMARTISTID"),
+    ("MusicBrainz Release Track Id", "MUSICBRAINZ_RELEASETRACKID"),
+    ("MusicBrainz Album Status", "RELEASESTATUS"),
+    ("CATALOGNUMBER", "CATALOGNUMBER"),
+    ("BARCODE", "BARCODE"),
+];
+
+fn normalize_freeform_name(name: &str) -> String {
+    FREEFORM_NAMES
+        .iter()
+        .find(|(freeform, _)| freeform.eq_ignore_ascii_case(name))
+        .map(|(_, vorbis)| vorbis.to_string())
+        .unwrap_or_else(|| name.to_uppercase())
+}
+
+/// Reads the free-form text tags of the audio file at `path`. For formats
+/// `audiotags` can't read this includes the standard text tags (`TITLE`,
+/// `ARTIST`, `ALBUM`, ...) too. Unreadable files yield an empty map.
+pub fn read_raw_tags(path: &Path, format: AudioFormat) -> RawTags {
+    let tags = match format {
+        AudioFormat::Flac => read_flac_tags(path),
+        AudioFormat::Mp3 => read_id3_tags(id3::Tag::read_from_path(path)),
+        AudioFormat::Mp4 => read_mp4_tags(path),
+        AudioFormat::Vorbis | AudioFormat::Opus => read_ogg_tags(path),
+        AudioFormat::Wav => read_wav_tags(path),
+        AudioFormat::Aiff => read_id3_tags(id3::Tag::read_from_path(path)),
+        AudioFormat::WavPack | AudioFormat::Ape => read_ape_tags(path),
+        AudioFormat::Dsf => read_dsf_tags(path),
+        AudioFormat::Dff => read_dff_tags(path),
+    };
+
+    tags.unwrap_or_else(|err| {
+        errln!("Failed to read tags from {}: {:?}", path.display(), err);
+        RawTags::new()
+    })
+}
+
+/// Parses the body of a Vorbis comment block (as found in FLAC and Ogg).
+pub fn parse_vorbis_comments(block: &[u8], tags: &mut RawTags) {
+    let Some(vendor_len) = read_u32_le(block, 0) else {
+        return;
+    };
+    let mut offset = 4 + vendor_len as usize;
+    let Some(count) = read_u32_le(block, offset) else {
+        return;
+    };
+    offset += 4;
+
+    for _ in 0..count {
+        let Some(len) = read_u32_le(block, offset) else {
+            return;
+        };
+        offset += 4;
+        let Some(comment) = block.get(offset..offset + len as usize) else {
+            return;
+        };
+        offset += len as usize;
+
+        let comment = String::from_utf8_lossy(comment);
+        if let Some((key, value)) = comment.split_once('=') {
+            tags.entry(key.to_uppercase())
+                .or_insert_with(|| value.to_string());
+        }
+    }
+}
+
+/// Skips a leading ID3v2 tag, which some encoders prepend to FLAC files.
+fn skip_id3v2(file: &mut File) -> std::io::Result<()> {
+    let mut header = [0u8; 10];
+    file.read_exact(&mut header)?;
+
+    if &header[..3] == b"ID3" {
+        file.seek(SeekFrom::Start(10 + id3v2_size(&header)))?;
+    } else {
+        file.seek(SeekFrom::Start(0))?;
+    }
+
+    Ok(())
+}
+
+fn read_flac_tags(path: &Path) -> std::io::Result<RawTags> {
+    let mut tags = RawTags::new();
+    let mut file = File::open(path)?;
+    skip_id3v2(&mut file)?;
+
+    let mut magic = [0u8; 4];
+    file.read_exact(&mut magic)?;
+    if &magic != b"fLaC" {
+        return Ok(tags);
+    }
+
+    loop {
+        let mut header = [0u8; 4];
+        file.read_exact(&mut header)?;
+        let is_last = header[0] & 0x80 != 0;
+        let block_type = header[0] & 0x7f;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]);
+
+        if block_type == 4 {
+            let mut block = vec![0u8; len as usize];
+            file.read_exact(&mut block)?;
+            parse_vorbis_comments(&block, &mut tags);
+        } else {
+            file.seek(SeekFrom::Current(i64::from(len)))?;
+        }
+
+        if is_last {
+            break;
+        }
+    }
+
+    Ok(tags)
+}
+
+/// ID3v2 text frames and their Vorbis comment names.
+const ID3_FRAMES: &[(&str, &str)] = &[
+    ("TIT2", "TITLE"),
+    ("TPE1", "ARTIST"),
+    ("TPE2", "ALBUMARTIST"),
+    ("TALB", "ALBUM"),
+    ("TDRC", "DATE"),
+    ("TYER", "DATE"),
+    ("TRCK", "TRACKNUMBER"),
+    ("TPOS", "DISCNUMBER"),
+    ("TCON", "GENRE"),
+];
+
+fn read_id3_tags(tag: id3::Result<id3::Tag>) -> std::io::Result<RawTags> {
+    let tag = match tag {
+        Ok(tag) => tag,
+        Err(id3::Error {
+            kind: id3::ErrorKind::NoTag,
+            ..
+        }) => return Ok(RawTags::new()),
+        Err(err) => return Err(std::io::Error::other(err)),
+    };
+
+    let mut tags = tag
+        .extended_texts()
+        .map(|text| {
+            (
+                normalize_freeform_name(&text.description),
+                text.value.clone(),
+            )
+        })
+        .collect::<RawTags>();
+
+    for (frame_id, name) in ID3_FRAMES {
+        if let Some(text) = tag.get(frame_id).and_then(|frame| frame.content().text()) {
+            tags.entry(name.to_string())
+                .or_insert_with(|| text.to_string());
+        }
+    }
+
+    if let Some(comment) = tag.comments().next() {
+        tags.entry("COMMENT".to_string())
+            .or_insert_with(|| comment.text.clone());
+    }
+
+    Ok(tags)
+}
+
+/// Reads the comment header, the second packet of an Ogg Vorbis or Opus
+/// stream.
+fn read_ogg_tags(path: &Path) -> std::io::Result<RawTags> {
+    let mut tags = RawTags::new();
+    let mut file = File::open(path)?;
+    let mut packets = vec![vec![]];
+
+    // The comment header normally ends within the first few pages.
+    for _ in 0..64 {
+        let mut header = [0u8; 27];
+        if file.read_exact(&mut header).is_err() || &header[..4] != b"OggS" {
+            break;
+        }
+
+        let mut segments = vec![0u8; header[26] as usize];
+        file.read_exact(&mut segments)?;
+
+        for segment_len in segments {
+            let mut segment = vec![0u8; segment_len as usize];
+            file.read_exact(&mut segment)?;
+            packets.last_mut().unwrap().extend_from_slice(&segment);
+
+            // A segment shorter than 255 bytes ends the packet.
+            if segment_len < 255 {
+                packets.push(vec![]);
+            }
+        }
+
+        if packets.len() > 2 {
+            break;
+        }
+    }
+
+    let Some(comments) = packets.get(1) else {
+        return Ok(tags);
+    };
+
+    let body = if let Some(body) = comments.strip_prefix(b"\x03vorbis") {
+        body
+    } else if let Some(body) = comments.strip_prefix(b"OpusTags") {
+        body
+    } else {
+        return Ok(tags);
+    };
+
+    parse_vorbis_comments(body, &mut tags);
+
+    Ok(tags)
+}
+
+/// RIFF INFO chunk ids and their Vorbis comment names.
+const RIFF_INFO_NAMES: &[(&[u8; 4], &str)] = &[
+    (b"INAM", "TITLE"),
+    (b"IART", "ARTIST"),
+    (b"IPRD", "ALBUM"),
+    (b"ICRD", "DATE"),
+    (b"ITRK", "TRACKNUMBER"),
+    (b"IPRT", "TRACKNUMBER"),
+    (b"IGNR", "GENRE"),
+    (b"ICMT", "COMMENT"),
+];
+
+/// Reads the ID3 chunk of a WAV file, filling gaps from its RIFF INFO list.
+fn read_wav_tags(path: &Path) -> std::io::Result<RawTags> {
+    let mut tags = read_id3_tags(id3::Tag::read_from_path(path)).unwrap_or_else(|_| RawTags::new());
+
+    let mut file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+    let mut header = [0u8; 12];
+    file.read_exact(&mut header)?;
+
+    loop {
+        let mut chunk_header = [0u8; 8];
+        if file.read_exact(&mut chunk_header).is_err() {
+            break;
+        }
+        let len = u64::from(read_u32_le(&chunk_header, 4).unwrap());
+
+        if &chunk_header[..4] != b"LIST" {
+            // Chunks are padded to an even length.
+            file.seek(SeekFrom::Current((len + len % 2) as i64))?;
+            continue;
+        }
+
+        if len > file_len {
+            break;
+        }
+
+        let mut list = vec![0u8; len as usize];
+        file.read_exact(&mut list)?;
+        if !list.starts_with(b"INFO") {
+            continue;
+        }
+
+        let mut offset = 4;
+        while let Some(len) = read_u32_le(&list, offset + 4) {
+            let id = &list[offset..offset + 4];
+            let Some(value) = list.get(offset + 8..offset + 8 + len as usize) else {
+                break;
+            };
+
+            if let Some((_, name)) = RIFF_INFO_NAMES
+                .iter()
+                .find(|(info_id, _)| &info_id[..] == id)
+            {
+                let value = String::from_utf8_lossy(value);
+                tags.entry(name.to_string())
+                    .or_insert_with(|| value.trim_end_matches('\0').to_string());
+            }
+
+            offset += 8 + len as usize + len as usize % 2;
+        }
+    }
+
+    Ok(tags)
+}
+
+/// Reads an APEv2 tag (used by WavPack and Monkey's Audio) from the end of
+/// the file, in front of an ID3v1 tag if there is one.
+fn read_ape_tags(path: &Path) -> std::io::Result<RawTags> {
+    let mut tags = RawTags::new();
+    let mut file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+
+    let mut footer = [0u8; 32];
+    let mut footer_end = file_len;
+
+    for id3v1_len in [0, 128] {
+        if file_len < 32 + id3v1_len {
+            return Ok(tags);
+        }
+        file.seek(SeekFrom::Start(file_len - id3v1_len - 32))?;
+        file.read_exact(&mut footer)?;
+        footer_end = file_len - id3v1_len;
+        if &footer[..8] == b"APETAGEX" {
+            break;
+        }
+    }
+
+    if &footer[..8] != b"APETAGEX" {
+        return Ok(tags);
+    }
+
+    // The size covers the items and the footer, but not the header.
+    let size = u64::from(read_u32_le(&footer, 12).unwrap());
+    let count = read_u32_le(&footer, 16).unwrap();
+
+    if size < 32 || size > footer_end {
+        return Ok(tags);
+    }
+
+    file.seek(SeekFrom::Start(footer_end - size))?;
+    let mut items = vec![0u8; (size - 32) as usize];
+    file.read_exact(&mut items)?;
+
+    let mut offset = 0;
+    for _ in 0..count {
+        let (Some(len), Some(flags)) =
+            (read_u32_le(&items, offset), read_u32_le(&items, offset + 4))
+        else {
+            break;
+        };
+        offset += 8;
+
+        let Some(key_len) = items[offset..].iter().position(|byte| *byte == 0) else {
+            break;
+        };
+        let key = String::from_utf8_lossy(&items[offset..offset + key_len]).to_uppercase();
+        offset += key_len + 1;
+
+        let Some(value) = items.get(offset..offset + len as usize) else {
+            break;
+        };
+        offset += len as usize;
+
+        // Bits 1-2 give the item type; 0 is UTF-8 text.
+        if flags & 0b110 != 0 {
+            continue;
+        }
+
+        let name = match key.as_str() {
+            "YEAR" => "DATE".to_string(),
+            "TRACK" => "TRACKNUMBER".to_string(),
+            "ALBUM ARTIST" => "ALBUMARTIST".to_string(),
+            _ => normalize_freeform_name(&key),
+        };
+
+        // Multiple values are separated by NULs.
+        let value = String::from_utf8_lossy(value);
+        tags.entry(name)
+            .or_insert_with(|| value.split('\0').next().unwrap_or_default().to_string());
+    }
+
+    Ok(tags)
+}
+
+/// DSF files point to an ID3v2 tag at the end of the file from their header.
+fn read_dsf_tags(path: &Path) -> std::io::Result<RawTags> {
+    let mut file = File::open(path)?;
+    let mut header = [0u8; 28];
+    file.read_exact(&mut header)?;
+
+    let metadata_offset = u64::from_le_bytes(header[20..28].try_into().unwrap());
+    if metadata_offset == 0 {
+        return Ok(RawTags::new());
+    }
+
+    file.seek(SeekFrom::Start(metadata_offset))?;
+    read_id3_tags(id3::Tag::read_from2(file))
+}
+
+/// DFF files can carry an (unofficial) top-level `ID3 ` chunk.
+fn read_dff_tags(path: &Path) -> std::io::Result<RawTags> {
+    let mut file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+    let mut header = [0u8; 16];
+    file.read_exact(&mut header)?;
+
+    loop {
+        let mut chunk_header = [0u8; 12];
+        if file.read_exact(&mut chunk_header).is_err() {
+            return Ok(RawTags::new());
+        }
+        let len = u64::from_be_bytes(chunk_header[4..12].try_into().unwrap());
+
+        if len > file_len {
+            return Ok(RawTags::new());
+        }
+
+        if &chunk_header[..4] == b"ID3 " {
+            let mut chunk = vec![0u8; len as usize];
+            file.read_exact(&mut chunk)?;
+            return read_id3_tags(id3::Tag::read_from2(std::io::Cursor::new(chunk)));
+        }
+
+        file.seek(SeekFrom::Current((len + len % 2) as i64))?;
+    }
+}
+
+/// Splits `data` into its child MP4 boxes as `(type, body)` pairs.
+pub fn mp4_boxes(mut data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut boxes = vec![];
+
+    while data.len() >= 8 {
+        let size = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+        let box_type: [u8; 4] = data[4..8].try_into().unwrap();
+        let (header_len, size) = match size {
+            0 => (8, data.len()),
+            1 if data.len() >= 16 => (
+                16,
+                u64::from_be_bytes(data[8..16].try_into().unwrap()) as usize,
+            ),
+            size => (8, size),
+        };
+
+        if size < header_len || size > data.len() {
+            break;
+        }
+
+        boxes.push((box_type, &data[header_len..size]));
+        data = &data[size..];
+    }
+
+    boxes
+}
+
+pub fn find_mp4_box<'a>(data: &'a [u8], box_type: &[u8; 4]) -> Option<&'a [u8]> {
+    mp4_boxes(data)
+        .into_iter()
+        .find(|(child_type, _)| child_type == box_type)
+        .map(|(_, body)| body)
+}
+
+/// Reads the top-level `moov` box into memory, seeking over the (potentially
+/// huge) media data.
+pub fn read_mp4_moov(file: &mut File) -> std::io::Result<Option<Vec<u8>>> {
+    let file_len = file.metadata()?.len();
+    let mut position = 0;
+
+    while position + 8 <= file_len {
+        file.seek(SeekFrom::Start(position))?;
+        let mut header = [0u8; 8];
+        file.read_exact(&mut header)?;
+        let mut header_len = 8;
+        let size = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            0 => file_len - position,
+            1 => {
+                let mut large_size = [0u8; 8];
+                file.read_exact(&mut large_size)?;
+                header_len = 16;
+                u64::from_be_bytes(large_size)
+            }
+            size => u64::from(size),
+        };
+
+        if size < header_len {
+            break;
+        }
+
+        if &header[4..] == b"moov" {
+            let mut moov = vec![0u8; (size - header_len) as usize];
+            file.read_exact(&mut moov)?;
+            return Ok(Some(moov));
+        }
+
+        position += size;
+    }
+
+    Ok(None)
+}
+
+fn read_mp4_tags(path: &Path) -> std::io::Result<RawTags> {
+    let mut tags = RawTags::new();
+    let mut file = File::open(path)?;
+
+    let Some(moov) = read_mp4_moov(&mut file)? else {
+        return Ok(tags);
+    };
+
+    // `meta` is a full box: skip its version/flags before the children.
+    let Some(ilst) = find_mp4_box(&moov, b"udta")
+        .and_then(|udta| find_mp4_box(udta, b"meta"))
+        .and_then(|meta| meta.get(4..))
+        .and_then(|meta| find_mp4_box(meta, b"ilst"))
+    else {
+        return Ok(tags);
+    };
+
+    for (item_type, item) in mp4_boxes(ilst) {
+        if &item_type != b"----" {
+            continue;
+        }
+
+        let mut name = None;
+        let mut value = None;
+
+        for (child_type, child) in mp4_boxes(item) {
+            match &child_type {
+                // Skip version/flags.
+                b"name" => name = child.get(4..).map(String::from_utf8_lossy),
+                // Skip the type indicator and locale.
+                b"data" if value.is_none() => value = child.get(8..).map(String::from_utf8_lossy),
+                _ => {}
+            }
+        }
+
+        if let (Some(name), Some(value)) = (name, value) {
+            tags.entry(normalize_freeform_name(&name))
+                .or_insert_with(|| value.to_string());
+        }
+    }
+
+    Ok(tags)
+}
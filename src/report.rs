@@ -0,0 +1,161 @@
+//! `--report <file.json>`: a machine-readable summary of a run.
+//!
+//! Each album is processed inside [`collect`], which gives it an
+//! [`AlbumReport`] that the code handling it fills in through [`record`].
+//! Lines written with `errln!` and `warnln!` are recorded as its errors and
+//! warnings.
+
+use serde::Serialize;
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+tokio::task_local! {
+    static ALBUM_REPORT: Arc<Mutex<AlbumReport>>;
+}
+
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AlbumStatus {
+    /// Copied to the target for the first time.
+    Copied,
+    /// Files were added to or changed in the target copy.
+    Updated,
+    /// Processed, with nothing to copy.
+    #[default]
+    UpToDate,
+    /// Skipped because the index says it hasn't changed since the last run.
+    Unchanged,
+    /// Deliberately not copied (duplicate, lower quality, no audio).
+    Skipped,
+    /// Errors kept it from being copied.
+    Failed,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct CoverReport {
+    pub provider: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct AlbumReport {
+    pub source: PathBuf,
+    pub target: Option<PathBuf>,
+    pub status: AlbumStatus,
+    pub files_copied: Vec<String>,
+    pub files_changed: Vec<String>,
+    /// Source files already up to date in the target.
+    pub files_skipped: Vec<String>,
+    pub cover: Option<CoverReport>,
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+    pub duration_ms: u64,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct Totals {
+    pub albums: usize,
+    pub copied: usize,
+    pub updated: usize,
+    pub up_to_date: usize,
+    pub unchanged: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub files_copied: usize,
+    pub files_changed: usize,
+    pub files_skipped: usize,
+    pub covers: usize,
+    pub errors: usize,
+    pub warnings: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RunReport {
+    /// Seconds since the Unix epoch.
+    pub started_at: u64,
+    pub duration_ms: u64,
+    pub totals: Totals,
+    pub albums: Vec<AlbumReport>,
+}
+
+impl RunReport {
+    pub fn new(started_at: SystemTime, albums: Vec<AlbumReport>) -> Self {
+        let mut totals = Totals {
+            albums: albums.len(),
+            ..Default::default()
+        };
+
+        for album in &albums {
+            match album.status {
+                AlbumStatus::Copied => totals.copied += 1,
+                AlbumStatus::Updated => totals.updated += 1,
+                AlbumStatus::UpToDate => totals.up_to_date += 1,
+                AlbumStatus::Unchanged => totals.unchanged += 1,
+                AlbumStatus::Skipped => totals.skipped += 1,
+                AlbumStatus::Failed => totals.failed += 1,
+            }
+            totals.files_copied += album.files_copied.len();
+            totals.files_changed += album.files_changed.len();
+            totals.files_skipped += album.files_skipped.len();
+            totals.covers += usize::from(album.cover.is_some());
+            totals.errors += album.errors.len();
+            totals.warnings += album.warnings.len();
+        }
+
+        Self {
+            started_at: started_at
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs()),
+            duration_ms: started_at
+                .elapsed()
+                .map_or(0, |duration| duration.as_millis() as u64),
+            totals,
+            albums,
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        let json = serde_json::to_vec_pretty(self).map_err(std::io::Error::other)?;
+        std::fs::write(path, json)
+    }
+}
+
+/// Updates the report of the album being processed, if there is one.
+pub fn record(update: impl FnOnce(&mut AlbumReport)) {
+    let _ = ALBUM_REPORT.try_with(|report| update(&mut report.lock().unwrap()));
+}
+
+/// Runs `future`, which processes the album at `source`, and returns its
+/// output along with what it recorded.
+pub async fn collect<F: Future>(source: PathBuf, future: F) -> (F::Output, AlbumReport) {
+    let report = Arc::new(Mutex::new(AlbumReport {
+        source,
+        ..Default::default()
+    }));
+    let start = Instant::now();
+
+    let value = ALBUM_REPORT.scope(report.clone(), future).await;
+
+    let mut report = report.lock().unwrap().clone();
+    report.duration_ms = start.elapsed().as_millis() as u64;
+
+    (value, report)
+}
+
+/// Like `tokio::task::spawn_blocking`, but what `f` records and logs goes to
+/// the album being processed.
+pub async fn spawn_blocking<R: Send + 'static>(f: impl FnOnce() -> R + Send + 'static) -> R {
+    let f = crate::output::carry(f);
+
+    match ALBUM_REPORT.try_with(Arc::clone) {
+        Ok(report) => tokio::task::spawn_blocking(move || ALBUM_REPORT.sync_scope(report, f))
+            .await
+            .unwrap(),
+        Err(_) => tokio::task::spawn_blocking(f).await.unwrap(),
+    }
+}
@@ -0,0 +1,339 @@
+//! Transcoded portable copy of the library (`--transcode-target`).
+//!
+//! Albums are laid out like the target, with every audio file re-encoded to
+//! one codec and bitrate by a local encoder process and the cover downscaled.
+//! A file is only transcoded again when its source is newer than the copy.
+
+use clap::ValueEnum;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+use tokio::sync::Semaphore;
+
+use crate::{
+    exclude::Exclude,
+    formats::{self, AudioFormat},
+    report,
+};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    Opus,
+    Mp3,
+}
+
+impl Codec {
+    fn extension(self) -> &'static str {
+        match self {
+            Codec::Opus => "opus",
+            Codec::Mp3 => "mp3",
+        }
+    }
+
+    fn format(self) -> AudioFormat {
+        match self {
+            Codec::Opus => AudioFormat::Opus,
+            Codec::Mp3 => AudioFormat::Mp3,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoder {
+    Ffmpeg,
+    /// Only encodes Opus, from FLAC, WAV and AIFF sources. Other sources still
+    /// go through ffmpeg.
+    Opusenc,
+}
+
+impl Encoder {
+    fn program(self) -> &'static str {
+        match self {
+            Encoder::Ffmpeg => "ffmpeg",
+            Encoder::Opusenc => "opusenc",
+        }
+    }
+}
+
+/// How files are encoded for the portable copy.
+#[derive(Clone, Copy)]
+pub struct Profile {
+    pub codec: Codec,
+    /// Bitrate in kbps.
+    pub bitrate: u32,
+    pub encoder: Encoder,
+    /// Longest side of the downscaled cover, in pixels.
+    pub cover_size: u32,
+}
+
+pub struct TranscodeOptions {
+    pub target_dir: PathBuf,
+    pub profile: Profile,
+    /// Limits the encoder processes running at once across all albums.
+    pub jobs: Semaphore,
+}
+
+fn not_found(program: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{program} not found: install it or drop --transcode-target"),
+    )
+}
+
+/// Runs `command`, turning a missing program or a failed exit into an error.
+fn run(command: &mut Command) -> io::Result<()> {
+    let program = command.get_program().to_string_lossy().to_string();
+
+    let status = command
+        .stdin(Stdio::null())
+        .status()
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => not_found(&program),
+            _ => err,
+        })?;
+
+    if !status.success() {
+        return Err(io::Error::other(format!("{program} exited with {status}")));
+    }
+
+    Ok(())
+}
+
+impl Profile {
+    /// Checks that the encoders this profile needs are installed. ffmpeg is
+    /// always needed, for the cover and for sources opusenc can't read.
+    pub fn check_encoders(&self) -> io::Result<()> {
+        let mut programs = vec![Encoder::Ffmpeg];
+        if self.encoder == Encoder::Opusenc {
+            if self.codec != Codec::Opus {
+                return Err(io::Error::other("opusenc can only encode Opus"));
+            }
+            programs.push(Encoder::Opusenc);
+        }
+
+        for encoder in programs {
+            let mut command = Command::new(encoder.program());
+            command
+                .arg(match encoder {
+                    Encoder::Ffmpeg => "-version",
+                    Encoder::Opusenc => "--version",
+                })
+                .stdout(Stdio::null())
+                .stderr(Stdio::null());
+            run(&mut command)?;
+        }
+
+        Ok(())
+    }
+
+    fn encode(
+        &self,
+        source: &Path,
+        format: AudioFormat,
+        cover: Option<&Path>,
+        target: &Path,
+    ) -> io::Result<()> {
+        let bitrate = format!("{}k", self.bitrate);
+
+        if self.encoder == Encoder::Opusenc
+            && matches!(
+                format,
+                AudioFormat::Flac | AudioFormat::Wav | AudioFormat::Aiff
+            )
+        {
+            // opusenc carries over FLAC tags by itself.
+            let mut command = Command::new("opusenc");
+            command
+                .args(["--quiet", "--bitrate", &self.bitrate.to_string()])
+                .arg(source);
+            if let Some(cover) = cover {
+                command.arg("--picture").arg(cover);
+            }
+            command.arg(target);
+            return run(&mut command);
+        }
+
+        let mut command = Command::new("ffmpeg");
+        command
+            .args(["-nostdin", "-v", "error", "-y", "-i"])
+            .arg(source);
+
+        match (self.codec, cover) {
+            (Codec::Mp3, Some(cover)) => {
+                command.arg("-i").arg(cover).args([
+                    "-map",
+                    "0:a:0",
+                    "-map",
+                    "1:v:0",
+                    "-c:v",
+                    "copy",
+                    "-disposition:v",
+                    "attached_pic",
+                ]);
+            }
+            // The Ogg muxer can't embed pictures, so Opus players get the
+            // cover file next to the tracks.
+            _ => {
+                command.args(["-map", "0:a:0"]);
+            }
+        }
+
+        command.args(["-map_metadata", "0", "-b:a", &bitrate]);
+        match self.codec {
+            Codec::Opus => command.args(["-c:a", "libopus"]),
+            Codec::Mp3 => command.args(["-c:a", "libmp3lame", "-id3v2_version", "3"]),
+        };
+
+        run(command.arg(target))
+    }
+
+    fn scale_cover(&self, source: &Path, target: &Path) -> io::Result<()> {
+        let size = self.cover_size;
+        let mut command = Command::new("ffmpeg");
+        command
+            .args(["-nostdin", "-v", "error", "-y", "-i"])
+            .arg(source)
+            .args([
+                "-vf",
+                &format!("scale={size}:{size}:force_original_aspect_ratio=decrease"),
+                "-frames:v",
+                "1",
+            ])
+            .arg(target);
+        run(&mut command)
+    }
+}
+
+/// Whether `target` is missing or older than `source`.
+fn is_stale(source: &Path, target: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified());
+
+    match (modified(source), modified(target)) {
+        (Ok(source), Ok(target)) => source > target,
+        _ => true,
+    }
+}
+
+/// Writes `target` through a hidden temporary file (keeping the extension,
+/// which the encoders go by) so an interrupted encode leaves nothing behind.
+fn write_atomically(target: &Path, write: impl FnOnce(&Path) -> io::Result<()>) -> io::Result<()> {
+    let name = target.file_name().unwrap().to_str().unwrap();
+    let temp_file = target.with_file_name(format!(".moosicbox-tmp.{name}"));
+
+    write(&temp_file)
+        .and_then(|_| fs::rename(&temp_file, target))
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&temp_file);
+        })
+}
+
+/// What [`transcode_album`] wrote to the album directory.
+pub struct Transcoded {
+    pub written: Vec<String>,
+    /// Source files that couldn't be transcoded.
+    pub failed: Vec<String>,
+}
+
+/// Transcodes the album at `source` into `album_dir`, leaving out what
+/// `exclude` matches, or returns `None` if the album couldn't be transcoded
+/// at all.
+pub async fn transcode_album(
+    options: &TranscodeOptions,
+    source: &Path,
+    album_dir: &Path,
+    exclude: &Exclude,
+) -> Option<Transcoded> {
+    if let Err(err) = fs::create_dir_all(album_dir) {
+        errln!(
+            "Failed to create {}: {:?}",
+            album_dir.to_str().unwrap(),
+            err
+        );
+        return None;
+    }
+
+    let files = fs::read_dir(source)
+        .ok()?
+        .filter_map(|p| p.ok())
+        .map(|p| p.path())
+        .filter(|path| !exclude.matches(path))
+        .collect::<Vec<_>>();
+
+    let mut written = vec![];
+    let mut failed = vec![];
+
+    let cover = files.iter().find(|path| {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("cover."))
+    });
+    let scaled_cover = album_dir.join("cover.jpg");
+
+    if let Some(cover) = cover {
+        if is_stale(cover, &scaled_cover) {
+            let _permit = options.jobs.acquire().await.unwrap();
+            let (profile, source, target) = (options.profile, cover.clone(), scaled_cover.clone());
+            let scaled = report::spawn_blocking(move || {
+                write_atomically(&target, |temp| profile.scale_cover(&source, temp))
+            })
+            .await;
+
+            match scaled {
+                Ok(()) => written.push("cover.jpg".to_string()),
+                Err(err) => {
+                    errln!("Failed to scale {}: {}", cover.to_str().unwrap(), err);
+                    failed.push(cover.file_name().unwrap().to_str().unwrap().to_string());
+                }
+            }
+        }
+    }
+    let cover = cover.is_some().then_some(scaled_cover.as_path());
+
+    let encodes = files
+        .iter()
+        .filter_map(|path| Some((path, formats::detect(path)?)))
+        .filter_map(|(path, format)| {
+            let name = path.file_stem()?.to_str()?;
+            let target_name = format!("{name}.{}", options.profile.codec.extension());
+            let target = album_dir.join(&target_name);
+
+            is_stale(path, &target).then_some((path, format, target_name, target))
+        })
+        .map(|(path, format, target_name, target)| async move {
+            let _permit = options.jobs.acquire().await.unwrap();
+            let profile = options.profile;
+            let (source, cover) = (path.clone(), cover.map(Path::to_path_buf));
+
+            let encoded = report::spawn_blocking(move || {
+                write_atomically(&target, |temp| {
+                    // Already in the codec, so re-encoding would only lose
+                    // quality.
+                    if format == profile.codec.format() {
+                        fs::copy(&source, temp).map(|_| ())
+                    } else {
+                        profile.encode(&source, format, cover.as_deref(), temp)
+                    }
+                })
+            })
+            .await;
+
+            match encoded {
+                Ok(()) => Ok(target_name),
+                Err(err) => {
+                    errln!("Failed to transcode {}: {}", path.to_str().unwrap(), err);
+                    Err(path.file_name().unwrap().to_str().unwrap().to_string())
+                }
+            }
+        });
+
+    for encoded in futures::future::join_all(encodes).await {
+        match encoded {
+            Ok(target_name) => written.push(target_name),
+            Err(source_name) => failed.push(source_name),
+        }
+    }
+
+    Some(Transcoded { written, failed })
+}
@@ -0,0 +1,226 @@
+//! `verify`: audits the target copies of indexed albums for bit rot.
+//!
+//! Every file is hashed and compared with the manifest recorded in the
+//! library index when it was copied, and FLAC files are decoded and checked
+//! against the MD5 of the audio stored in their STREAMINFO block. Files
+//! copied before the manifest existed are added to it once they pass the
+//! FLAC check. Files linked to the source rather than copied are skipped, as
+//! they change along with it.
+
+use md5::{Digest, Md5};
+use std::{collections::HashMap, fs, path::Path};
+
+use crate::{
+    index::{self, LibraryIndex, ManifestEntry},
+    journal,
+};
+
+enum Problem {
+    Missing,
+    Mismatch,
+    FlacMd5Mismatch,
+    Unreadable(String),
+}
+
+impl Problem {
+    fn describe(&self) -> String {
+        match self {
+            Problem::Missing => "missing".to_string(),
+            Problem::Mismatch => "hash mismatch".to_string(),
+            Problem::FlacMd5Mismatch => "FLAC audio MD5 mismatch".to_string(),
+            Problem::Unreadable(err) => format!("unreadable ({err})"),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct VerifySummary {
+    pub checked: usize,
+    pub missing: usize,
+    pub mismatched: usize,
+    pub unreadable: usize,
+    pub restored: usize,
+    /// Files added to the manifest because they had no entry yet.
+    pub recorded: usize,
+    /// Files skipped because they're links to the source.
+    pub linked: usize,
+}
+
+impl VerifySummary {
+    /// Problems found and not restored.
+    pub fn unresolved(&self) -> usize {
+        self.missing + self.mismatched + self.unreadable - self.restored
+    }
+}
+
+fn is_flac(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("flac"))
+}
+
+/// Decodes the FLAC file at `path` and compares the MD5 of its audio with the
+/// one in STREAMINFO. `None` if the encoder didn't store one.
+fn check_flac_md5(path: &Path) -> Result<Option<bool>, claxon::Error> {
+    let mut reader = claxon::FlacReader::open(path)?;
+    let streaminfo = reader.streaminfo();
+
+    if streaminfo.md5sum == [0; 16] {
+        return Ok(None);
+    }
+
+    let bytes_per_sample = streaminfo.bits_per_sample.div_ceil(8) as usize;
+    let mut hasher = Md5::new();
+    let mut bytes = vec![];
+    let mut buffer = vec![];
+    let mut blocks = reader.blocks();
+
+    while let Some(block) = blocks.read_next_or_eof(buffer)? {
+        for sample in 0..block.duration() {
+            for channel in 0..block.channels() {
+                bytes.extend_from_slice(
+                    &block.sample(channel, sample).to_le_bytes()[..bytes_per_sample],
+                );
+            }
+        }
+        hasher.update(&bytes);
+        bytes.clear();
+        buffer = block.into_buffer();
+    }
+
+    Ok(Some(hasher.finalize()[..] == streaminfo.md5sum[..]))
+}
+
+/// Checks the file at `target` against its manifest entry, if any.
+fn check_file(target: &Path, expected: Option<&ManifestEntry>) -> Result<String, Problem> {
+    if !target.is_file() {
+        return Err(Problem::Missing);
+    }
+
+    let hash = index::hash_file(target).map_err(|err| Problem::Unreadable(err.to_string()))?;
+
+    if expected.is_some_and(|expected| expected.hash != hash) {
+        return Err(Problem::Mismatch);
+    }
+
+    if is_flac(target) {
+        match check_flac_md5(target) {
+            Ok(Some(false)) => return Err(Problem::FlacMd5Mismatch),
+            Ok(_) => {}
+            Err(err) => return Err(Problem::Unreadable(err.to_string())),
+        }
+    }
+
+    Ok(hash)
+}
+
+/// Copies `source` over `target` if the source is still intact: it matches
+/// the manifest, or (without a manifest entry) passes the FLAC check.
+fn restore(source: &Path, target: &Path, expected: Option<&ManifestEntry>) -> Option<String> {
+    let Ok(hash) = check_file(source, expected) else {
+        println!(
+            "\tsource {} is damaged or has changed",
+            source.to_str().unwrap()
+        );
+        return None;
+    };
+
+    if expected.is_none() && !is_flac(source) {
+        println!(
+            "\tno manifest entry to check {} against",
+            source.to_str().unwrap()
+        );
+        return None;
+    }
+
+    match journal::replace_file(source, target, journal::LinkMode::Copy) {
+        Ok(()) => Some(hash),
+        Err(err) => {
+            errln!("Failed to restore {}: {:?}", target.to_str().unwrap(), err);
+            None
+        }
+    }
+}
+
+/// Verifies every indexed album's target copy, restoring damaged files from
+/// the source when `restore_files` is set.
+pub fn verify(library: &LibraryIndex, restore_files: bool) -> VerifySummary {
+    let mut summary = VerifySummary::default();
+
+    for album in library.albums() {
+        let Some(album_dir) = &album.target_path else {
+            continue;
+        };
+
+        let manifest = library
+            .manifest(&album.source_path)
+            .into_iter()
+            .map(|entry| (entry.path.clone(), entry))
+            .collect::<HashMap<_, _>>();
+
+        let mut files = manifest.keys().cloned().collect::<Vec<_>>();
+        files.extend(
+            index::list_source_files(album_dir)
+                .into_iter()
+                .map(|file| file.path)
+                .filter(|path| !path.starts_with('.') && !manifest.contains_key(path)),
+        );
+        files.sort();
+
+        let mut updated_entries = vec![];
+
+        for file in files {
+            let target = album_dir.join(&file);
+            let expected = manifest.get(&file);
+
+            if journal::is_linked(&album.source_path.join(&file), &target) {
+                summary.linked += 1;
+                continue;
+            }
+            summary.checked += 1;
+
+            let problem = match check_file(&target, expected) {
+                Ok(hash) => {
+                    if expected.is_none() {
+                        summary.recorded += 1;
+                        updated_entries.push(ManifestEntry {
+                            path: file,
+                            size: fs::metadata(&target).map_or(0, |metadata| metadata.len()),
+                            hash,
+                        });
+                    }
+                    continue;
+                }
+                Err(problem) => problem,
+            };
+
+            println!("{}: {}", target.to_str().unwrap(), problem.describe());
+
+            match problem {
+                Problem::Missing => summary.missing += 1,
+                Problem::Mismatch | Problem::FlacMd5Mismatch => summary.mismatched += 1,
+                Problem::Unreadable(_) => summary.unreadable += 1,
+            }
+
+            if !restore_files {
+                continue;
+            }
+
+            if let Some(hash) = restore(&album.source_path.join(&file), &target, expected) {
+                println!("\trestored from {}", album.source_path.to_str().unwrap());
+                summary.restored += 1;
+                updated_entries.push(ManifestEntry {
+                    path: file,
+                    size: fs::metadata(&target).map_or(0, |metadata| metadata.len()),
+                    hash,
+                });
+            }
+        }
+
+        if let Err(err) = library.update_manifest(&album.source_path, &updated_entries) {
+            errln!("Failed to update library index: {:?}", err);
+        }
+    }
+
+    summary
+}
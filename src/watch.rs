@@ -0,0 +1,103 @@
+//! `watch`: organizes albums as they show up in the source directory.
+//!
+//! Filesystem events under the source are grouped by album directory, and an
+//! album is only handed on once nothing in it has changed for the quiet
+//! period, so downloads still being written are left alone.
+
+use notify::{
+    event::{AccessKind, AccessMode},
+    Event, EventKind, RecursiveMode, Watcher,
+};
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// The album directory (`<source>/<album>` or `<source>/<artist>/<album>`)
+/// that `path` belongs to.
+fn album_dir_for(source: &Path, path: &Path) -> Option<PathBuf> {
+    let mut components = path.strip_prefix(source).ok()?.components();
+    let top = source.join(components.next()?);
+
+    match components.next() {
+        Some(child) if top.join(child).is_dir() => Some(top.join(child)),
+        _ => Some(top),
+    }
+}
+
+/// Whether `event` means something under the source was written. Reads
+/// (including the organizer's own) are ignored.
+fn is_change(event: &Event) -> bool {
+    match event.kind {
+        EventKind::Access(AccessKind::Close(AccessMode::Write)) => true,
+        EventKind::Access(_) => false,
+        _ => true,
+    }
+}
+
+/// Watches `source` until the watcher fails, calling `process` with every
+/// album directory that has been quiet for `quiet`.
+pub async fn watch<F, Fut>(source: &Path, quiet: Duration, mut process: F) -> notify::Result<()>
+where
+    F: FnMut(PathBuf) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = tx.send(event);
+    })?;
+    watcher.watch(source, RecursiveMode::Recursive)?;
+
+    outln!(
+        "Watching {} (albums are processed after {}s without changes)",
+        source.to_str().unwrap(),
+        quiet.as_secs()
+    );
+
+    let mut pending = HashMap::<PathBuf, Instant>::new();
+
+    loop {
+        let due = pending
+            .values()
+            .min()
+            .map(|last_change| *last_change + quiet);
+
+        tokio::select! {
+            event = rx.recv() => match event {
+                Some(Ok(event)) if is_change(&event) => {
+                    for album_dir in event
+                        .paths
+                        .iter()
+                        .filter_map(|path| album_dir_for(source, path))
+                    {
+                        if !pending.contains_key(&album_dir) {
+                            outln!("Change detected in {}", album_dir.to_str().unwrap());
+                        }
+                        pending.insert(album_dir, Instant::now());
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(err)) => errln!("Watch error: {:?}", err),
+                None => return Ok(()),
+            },
+            _ = tokio::time::sleep_until(due.unwrap_or_else(Instant::now).into()), if due.is_some() => {
+                let now = Instant::now();
+                let ready = pending
+                    .iter()
+                    .filter(|(_, last_change)| now >= **last_change + quiet)
+                    .map(|(album_dir, _)| album_dir.clone())
+                    .collect::<Vec<_>>();
+
+                for album_dir in ready {
+                    pending.remove(&album_dir);
+
+                    if album_dir.is_dir() {
+                        process(album_dir).await;
+                    }
+                }
+            }
+        }
+    }
+}